@@ -1,12 +1,22 @@
 #![no_std]
 #![allow(unexpected_cfgs)]
 
-use spirv_std::glam::{Vec3, Vec4, vec4};
-use spirv_std::spirv;
+use spirv_std::glam::{vec2, vec3, vec4, Mat3, UVec3, Vec2, Vec3, Vec4, Vec4Swizzles};
+use spirv_std::{spirv, Image, Sampler};
+
+/// Must match `MaterialConstants` in `icarus`
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct MaterialConstants {
+    pub base_color: Vec4,
+}
 
 #[spirv(fragment)]
-pub fn main_fs(output: &mut Vec4) {
-    *output = vec4(1.0, 0.0, 0.0, 1.0);
+pub fn main_fs(
+    #[spirv(push_constant)] constants: &MaterialConstants,
+    output: &mut Vec4,
+) {
+    *output = constants.base_color;
 }
 
 #[spirv(vertex)]
@@ -15,4 +25,219 @@ pub fn main_vs(
     #[spirv(position)] gl_position: &mut Vec4,
 ) {
     *gl_position = in_position.extend(1.0);
-}
\ No newline at end of file
+}
+
+#[spirv(fragment)]
+pub fn textured_fs(
+    in_tex_coord: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &MaterialConstants,
+    output: &mut Vec4,
+) {
+    let color: Vec4 = texture.sample(*sampler, in_tex_coord);
+    *output = color * constants.base_color;
+}
+
+#[spirv(vertex)]
+pub fn textured_vs(
+    in_position: Vec3,
+    in_tex_coord: Vec2,
+    out_tex_coord: &mut Vec2,
+    #[spirv(position)] gl_position: &mut Vec4,
+) {
+    *out_tex_coord = in_tex_coord;
+    *gl_position = in_position.extend(1.0);
+}
+
+/// Must match `PostProcessConstants` in `icarus`
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct PostProcessConstants {
+    pub texel_size: Vec2,
+    pub strength: f32,
+    pub _padding: f32,
+}
+
+const LUMA: Vec3 = vec3(0.299, 0.587, 0.114);
+
+/// A single triangle covering the screen, drawn with three vertices and no vertex buffer
+#[spirv(vertex)]
+pub fn fullscreen_vs(
+    #[spirv(vertex_index)] vertex_index: i32,
+    out_tex_coord: &mut Vec2,
+    #[spirv(position)] gl_position: &mut Vec4,
+) {
+    let tex_coord = vec2(((vertex_index << 1) & 2) as f32, (vertex_index & 2) as f32);
+    *out_tex_coord = tex_coord;
+    *gl_position = vec4(tex_coord.x * 2.0 - 1.0, tex_coord.y * 2.0 - 1.0, 0.0, 1.0);
+}
+
+#[spirv(fragment)]
+pub fn tonemap_fs(
+    in_tex_coord: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostProcessConstants,
+    output: &mut Vec4,
+) {
+    let color: Vec4 = texture.sample(*sampler, in_tex_coord);
+    let exposed = color.xyz() * constants.strength;
+    // Reinhard
+    *output = (exposed / (exposed + Vec3::ONE)).extend(color.w);
+}
+
+#[spirv(fragment)]
+pub fn gamma_fs(
+    in_tex_coord: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostProcessConstants,
+    output: &mut Vec4,
+) {
+    let color: Vec4 = texture.sample(*sampler, in_tex_coord);
+    *output = color.xyz().powf(1.0 / constants.strength).extend(color.w);
+}
+
+#[spirv(fragment)]
+pub fn vignette_fs(
+    in_tex_coord: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostProcessConstants,
+    output: &mut Vec4,
+) {
+    let color: Vec4 = texture.sample(*sampler, in_tex_coord);
+    let offset = in_tex_coord - vec2(0.5, 0.5);
+    let falloff = (1.0 - constants.strength * offset.dot(offset) * 2.0).clamp(0.0, 1.0);
+    *output = (color.xyz() * falloff).extend(color.w);
+}
+
+#[spirv(fragment)]
+pub fn fxaa_fs(
+    in_tex_coord: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostProcessConstants,
+    output: &mut Vec4,
+) {
+    const REDUCE_MIN: f32 = 1.0 / 128.0;
+    const REDUCE_MUL: f32 = 1.0 / 8.0;
+    const SPAN_MAX: f32 = 8.0;
+
+    let texel = constants.texel_size;
+
+    let center: Vec4 = texture.sample(*sampler, in_tex_coord);
+    let nw: Vec4 = texture.sample(*sampler, in_tex_coord + vec2(-1.0, -1.0) * texel);
+    let ne: Vec4 = texture.sample(*sampler, in_tex_coord + vec2(1.0, -1.0) * texel);
+    let sw: Vec4 = texture.sample(*sampler, in_tex_coord + vec2(-1.0, 1.0) * texel);
+    let se: Vec4 = texture.sample(*sampler, in_tex_coord + vec2(1.0, 1.0) * texel);
+
+    let luma_nw = nw.xyz().dot(LUMA);
+    let luma_ne = ne.xyz().dot(LUMA);
+    let luma_sw = sw.xyz().dot(LUMA);
+    let luma_se = se.xyz().dot(LUMA);
+    let luma_m = center.xyz().dot(LUMA);
+
+    let luma_min = luma_m.min(luma_nw.min(luma_ne).min(luma_sw.min(luma_se)));
+    let luma_max = luma_m.max(luma_nw.max(luma_ne).max(luma_sw.max(luma_se)));
+
+    let dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+
+    let dir_reduce = ((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL).max(REDUCE_MIN);
+    let rcp_dir_min = 1.0 / (dir.x.abs().min(dir.y.abs()) + dir_reduce);
+    let dir = (dir * rcp_dir_min).clamp(Vec2::splat(-SPAN_MAX), Vec2::splat(SPAN_MAX)) * texel;
+
+    let a0: Vec4 = texture.sample(*sampler, in_tex_coord + dir * (1.0 / 3.0 - 0.5));
+    let a1: Vec4 = texture.sample(*sampler, in_tex_coord + dir * (2.0 / 3.0 - 0.5));
+    let b0: Vec4 = texture.sample(*sampler, in_tex_coord + dir * -0.5);
+    let b1: Vec4 = texture.sample(*sampler, in_tex_coord + dir * 0.5);
+
+    let rgb_a = 0.5 * (a0.xyz() + a1.xyz());
+    let rgb_b = rgb_a * 0.5 + 0.25 * (b0.xyz() + b1.xyz());
+    let luma_b = rgb_b.dot(LUMA);
+
+    let rgb = if luma_b < luma_min || luma_b > luma_max { rgb_a } else { rgb_b };
+    *output = rgb.extend(center.w);
+}
+
+/// Converts linear BT.709 colors to BT.2020 primaries
+const BT709_TO_BT2020: Mat3 = Mat3::from_cols(
+    vec3(0.6274, 0.0691, 0.0164),
+    vec3(0.3293, 0.9195, 0.0880),
+    vec3(0.0433, 0.0114, 0.8956),
+);
+
+/// Encodes for an HDR10 display, BT.2020 primaries with the SMPTE ST 2084 (PQ) curve.
+/// `strength` is the brightness of 1.0 in nits
+#[spirv(fragment)]
+pub fn pq_encode_fs(
+    in_tex_coord: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Image!(2D, type=f32, sampled),
+    #[spirv(descriptor_set = 0, binding = 1)] sampler: &Sampler,
+    #[spirv(push_constant)] constants: &PostProcessConstants,
+    output: &mut Vec4,
+) {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let color: Vec4 = texture.sample(*sampler, in_tex_coord);
+    // PQ covers 0 to 10000 nits
+    let luminance = (BT709_TO_BT2020 * color.xyz() * constants.strength / 10000.0).max(Vec3::ZERO);
+    let p = luminance.powf(M1);
+    *output = ((Vec3::splat(C1) + C2 * p) / (Vec3::ONE + C3 * p)).powf(M2).extend(color.w);
+}
+
+/// Push constants for `flow_field_cs`, passed with a matching type to `RenderResources::dispatch_with_constants`
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct FlowFieldConstants {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Axial offsets of a hex's six neighbours
+const HEX_NEIGHBOURS: [(i32, i32); 6] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, -1), (-1, 1)];
+
+/// One relaxation step of a flow field over an axial hex grid stored row by row.
+/// Each hex's distance becomes the cheapest way in from a neighbour, repeat until it settles
+#[spirv(compute(threads(64)))]
+pub fn flow_field_cs(
+    #[spirv(global_invocation_id)] id: UVec3,
+    #[spirv(push_constant)] constants: &FlowFieldConstants,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] costs: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] distances: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] next_distances: &mut [f32],
+) {
+    let idx = id.x;
+    if idx >= constants.width * constants.height {
+        return;
+    }
+
+    let q = (idx % constants.width) as i32;
+    let r = (idx / constants.width) as i32;
+
+    let mut distance = distances[idx as usize];
+
+    let mut neighbour = 0;
+    while neighbour < HEX_NEIGHBOURS.len() {
+        let (dq, dr) = HEX_NEIGHBOURS[neighbour];
+        let nq = q + dq;
+        let nr = r + dr;
+
+        if nq >= 0 && nr >= 0 && (nq as u32) < constants.width && (nr as u32) < constants.height {
+            let through_neighbour = distances[(nr as u32 * constants.width + nq as u32) as usize] + costs[idx as usize];
+            distance = distance.min(through_neighbour);
+        }
+
+        neighbour += 1;
+    }
+
+    next_distances[idx as usize] = distance;
+}
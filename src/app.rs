@@ -1,11 +1,19 @@
 mod shaders;
 mod config;
 mod settings;
+mod settings_file;
 mod core;
 mod capabilities;
 mod game;
+mod overrides;
+mod window;
 pub mod resources;
 
+pub use capabilities::{DeviceInfo, DeviceRequirements};
 pub use config::*;
 pub use core::*;
 pub use game::*;
+pub use overrides::*;
+pub use settings::*;
+pub use settings_file::*;
+pub use window::*;
@@ -1,20 +1,70 @@
+use crate::app::settings::{DisplayColorSpace, Msaa, Vsync};
 use std::cmp::{max, min};
+use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 use thiserror::Error;
 use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
-use vulkano::device::{DeviceExtensions, DeviceFeatures};
-use vulkano::format::Format;
-use vulkano::swapchain::{ColorSpace, CompositeAlpha, Surface};
+use vulkano::device::{DeviceExtensions, DeviceFeatures, QueueFlags};
+use vulkano::format::{Format, NumericFormat};
+use vulkano::image::{SampleCount, SampleCounts};
+use vulkano::memory::MemoryHeapFlags;
+use vulkano::swapchain::{ColorSpace, CompositeAlpha, PresentMode, Surface};
 use vulkano::{Validated, VulkanError};
 
 #[derive(Error, Debug)]
 pub enum CapabilityError {
     #[error("vulkan error! {0}")]
     VulkanError(#[from] Validated<VulkanError>),
-    #[error("GPU is unsuitable")]
-    Unsuitable,
+    #[error("GPU is unsuitable, {0}")]
+    Unsuitable(String),
 }
 
+/// A physical device and how well it suits the surface it was checked against
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub device_type: PhysicalDeviceType,
+    /// The device's score, or the reason it was rejected
+    pub suitability: Result<u32, String>,
+}
+
+impl DeviceInfo {
+    pub fn new(physical_device: &PhysicalDevice, suitability: Result<u32, String>) -> Self {
+        let properties = physical_device.properties();
+
+        Self {
+            name: properties.device_name.clone(),
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            device_type: properties.device_type,
+            suitability,
+        }
+    }
+
+    pub fn is_suitable(&self) -> bool {
+        self.suitability.is_ok()
+    }
+
+    /// Whether this is the device with the `(vendor_id, device_id)` pair
+    pub fn matches(&self, (vendor_id, device_id): (u32, u32)) -> bool {
+        self.vendor_id == vendor_id && self.device_id == device_id
+    }
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:04x}:{:04x})", self.name, self.vendor_id, self.device_id)
+    }
+}
+
+/// Parses a `vendor:device` pair of hex ids, as printed by `DeviceInfo`
+pub(crate) fn parse_device_id(id: &str) -> Option<(u32, u32)> {
+    let (vendor_id, device_id) = id.trim().split_once(':')?;
+
+    Some((u32::from_str_radix(vendor_id, 16).ok()?, u32::from_str_radix(device_id, 16).ok()?))
+}
 
 /// Capabilities describes everything that the GPU can do as well as a score to rank
 pub struct Capabilities {
@@ -23,66 +73,163 @@ pub struct Capabilities {
     score: u32,
     swapchain_images: u32,
     composite_alpha: CompositeAlpha,
-    image_format: (Format, ColorSpace),
+    surface_formats: Vec<(Format, ColorSpace)>,
+    sample_counts: SampleCounts,
+    present_modes: Vec<PresentMode>,
 }
 
+/// Presenting is impossible without these, so they're always required
 const REQUIRED_DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
     khr_swapchain: true,
     ..DeviceExtensions::empty()
 };
 
-const REQUIRED_DEVICE_FEATURES: DeviceFeatures = DeviceFeatures {
-    ..DeviceFeatures::empty()
-};
+/// What the game needs from a device. Devices missing anything required are rejected,
+/// optional features and extensions are enabled when supported and raise a device's score.
+/// Check `RenderResources::enabled_features` before relying on an optional one. The defaults only cover
+/// what the engine uses, games opt in to anything else, such as dynamic rendering, through `Config::device_requirements`
+#[derive(Clone, Debug)]
+pub struct DeviceRequirements {
+    pub required_features: DeviceFeatures,
+    pub optional_features: DeviceFeatures,
+    pub required_extensions: DeviceExtensions,
+    pub optional_extensions: DeviceExtensions,
+}
 
-const OPTIONAL_DEVICE_EXTENSIONS: DeviceExtensions = DeviceExtensions {
-    ..REQUIRED_DEVICE_EXTENSIONS
-};
+impl Default for DeviceRequirements {
+    fn default() -> Self {
+        Self {
+            required_features: DeviceFeatures::empty(),
+            // Only what the engine itself uses, textures are sampled anisotropically when it's there
+            optional_features: DeviceFeatures {
+                sampler_anisotropy: true,
+                ..DeviceFeatures::empty()
+            },
+            required_extensions: REQUIRED_DEVICE_EXTENSIONS,
+            optional_extensions: DeviceExtensions::empty(),
+        }
+    }
+}
 
-const OPTIONAL_DEVICE_FEATURES: DeviceFeatures = DeviceFeatures {
-    ..REQUIRED_DEVICE_FEATURES
-};
+impl DeviceRequirements {
+    fn required_extensions(&self) -> DeviceExtensions {
+        self.required_extensions.union(&REQUIRED_DEVICE_EXTENSIONS)
+    }
+}
 
-impl Capabilities {
-    pub fn for_device_on_surface(physical_device: &Arc<PhysicalDevice>, surface: &Arc<Surface>) -> Result<Self, CapabilityError> {
-        let mut score = 0;
+/// The names of everything turned on in a set of features or extensions
+fn enabled_names(set: impl IntoIterator<Item = (&'static str, bool)>) -> String {
+    set.into_iter()
+        .filter(|&(_name, enabled)| enabled)
+        .map(|(name, _enabled)| name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-        score += match physical_device.clone().properties().device_type {
-            PhysicalDeviceType::DiscreteGpu => 5,
-            PhysicalDeviceType::IntegratedGpu => 4,
-            PhysicalDeviceType::VirtualGpu => 3,
-            PhysicalDeviceType::Other => 2,
-            PhysicalDeviceType::Cpu => 1,
-            _ => 0
-        };
+/// Caps how much device local memory counts towards the score, past this more doesn't help
+const MAX_SCORED_MEMORY_GIB: u64 = 32;
 
+/// The score between one device type and the next, everything else is kept below it
+const DEVICE_TYPE_SCORE: u32 = 1000;
+
+impl Capabilities {
+    pub fn for_device_on_surface(
+        physical_device: &Arc<PhysicalDevice>,
+        surface: &Arc<Surface>,
+        requirements: &DeviceRequirements,
+    ) -> Result<Self, CapabilityError> {
         let caps = physical_device.surface_capabilities(&surface, Default::default())?;
 
         let swapchain_images = min(max(caps.min_image_count, 3), caps.max_image_count.unwrap_or(u32::MAX));
         let composite_alpha = caps.supported_composite_alpha.into_iter().next().unwrap();
-        let image_format = *physical_device.surface_formats(surface, Default::default())?.first().ok_or(CapabilityError::Unsuitable)?;
+        let surface_formats = physical_device.surface_formats(surface, Default::default())?;
+        if surface_formats.is_empty() {
+            return Err(CapabilityError::Unsuitable(String::from("the surface has no formats")));
+        }
+        let present_modes = physical_device.surface_present_modes(surface, Default::default())?
+            .into_iter()
+            .collect();
+
+        // We draw color and depth with the same number of samples
+        let properties = physical_device.properties();
+        let sample_counts = properties.framebuffer_color_sample_counts
+            .intersection(properties.framebuffer_depth_sample_counts);
+
+        let has_graphics_queue = physical_device
+            .queue_family_properties()
+            .iter()
+            .any(|queue_family_properties| queue_family_properties.queue_flags.contains(QueueFlags::GRAPHICS));
 
-        if
-        !physical_device.supported_features().contains(&REQUIRED_DEVICE_FEATURES)
-            || !physical_device.supported_extensions().contains(&OPTIONAL_DEVICE_EXTENSIONS)
-        {
-            Err(CapabilityError::Unsuitable)
+        let missing_features = requirements.required_features.difference(physical_device.supported_features());
+        let missing_extensions = requirements.required_extensions().difference(physical_device.supported_extensions());
+
+        if !has_graphics_queue {
+            Err(CapabilityError::Unsuitable(String::from("it has no graphics queue")))
+        } else if missing_features != DeviceFeatures::empty() {
+            Err(CapabilityError::Unsuitable(format!("it is missing required features {}", enabled_names(missing_features))))
+        } else if missing_extensions != DeviceExtensions::empty() {
+            Err(CapabilityError::Unsuitable(format!("it is missing required extensions {}", enabled_names(missing_extensions))))
         } else {
+            let optional_features = physical_device.supported_features().intersection(&requirements.optional_features);
+            let optional_extensions = physical_device.supported_extensions().intersection(&requirements.optional_extensions);
+
+            let score = Self::score_device(physical_device, &optional_features, &optional_extensions);
+
             Ok(Capabilities {
-                device_features: physical_device.supported_features().intersection(&OPTIONAL_DEVICE_FEATURES),
-                device_extensions: physical_device.supported_extensions().intersection(&OPTIONAL_DEVICE_EXTENSIONS),
+                device_features: requirements.required_features.union(&optional_features),
+                device_extensions: requirements.required_extensions().union(&optional_extensions),
                 swapchain_images,
                 composite_alpha,
-                image_format,
+                surface_formats,
+                sample_counts,
+                present_modes,
                 score,
             })
         }
     }
 
+    /// Ranks a suitable device, the device type dominates and the rest separates similar devices
+    fn score_device(physical_device: &PhysicalDevice, optional_features: &DeviceFeatures, optional_extensions: &DeviceExtensions) -> u32 {
+        let properties = physical_device.properties();
+
+        let device_type_score = match properties.device_type {
+            PhysicalDeviceType::DiscreteGpu => 5,
+            PhysicalDeviceType::IntegratedGpu => 4,
+            PhysicalDeviceType::VirtualGpu => 3,
+            PhysicalDeviceType::Other => 2,
+            PhysicalDeviceType::Cpu => 1,
+            _ => 0
+        } * DEVICE_TYPE_SCORE;
+
+        let device_local_memory: u64 = physical_device
+            .memory_properties()
+            .memory_heaps
+            .iter()
+            .filter(|heap| heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
+        let memory_score = min(device_local_memory >> 30, MAX_SCORED_MEMORY_GIB) as u32 * 10;
+
+        let optional_score = (
+            optional_features.into_iter().filter(|&(_name, enabled)| enabled).count()
+                + optional_extensions.into_iter().filter(|&(_name, enabled)| enabled).count()
+        ) as u32 * 20;
+
+        let image_score = properties.max_image_dimension2_d / 1024;
+        let api_version_score = properties.api_version.minor * 5;
+
+        // Clamped so no amount of memory or optional support lifts a device above a better type
+        let rest_score = min(memory_score + optional_score + image_score + api_version_score, DEVICE_TYPE_SCORE - 1);
+
+        device_type_score + rest_score
+    }
+
     pub fn score(&self) -> u32 { self.score }
 
-    pub fn required_features(&self) -> &DeviceFeatures { &self.device_features }
-    pub fn required_extensions(&self) -> &DeviceExtensions { &self.device_extensions }
+    /// Required features plus the supported optional ones
+    pub fn enabled_features(&self) -> &DeviceFeatures { &self.device_features }
+    /// Required extensions plus the supported optional ones
+    pub fn enabled_extensions(&self) -> &DeviceExtensions { &self.device_extensions }
 
     pub fn swapchain_images(&self) -> u32 {
         self.swapchain_images
@@ -92,7 +239,66 @@ impl Capabilities {
         &self.composite_alpha
     }
 
-    pub fn image_format(&self) -> &(Format, ColorSpace) {
-        &self.image_format
+    /// Every format and color space the surface can present
+    pub fn surface_formats(&self) -> &[(Format, ColorSpace)] {
+        &self.surface_formats
+    }
+
+    /// The best surface format for `color_space`, falling back to sRGB when it isn't supported
+    pub fn surface_format(&self, color_space: DisplayColorSpace) -> (Format, ColorSpace) {
+        let hdr_format = match color_space {
+            DisplayColorSpace::Srgb => None,
+            DisplayColorSpace::Hdr10 => self.find_surface_format(
+                ColorSpace::Hdr10St2084,
+                &[Format::A2B10G10R10_UNORM_PACK32, Format::A2R10G10B10_UNORM_PACK32],
+            ),
+            DisplayColorSpace::ExtendedSrgb => self.find_surface_format(
+                ColorSpace::ExtendedSrgbLinear,
+                &[Format::R16G16B16A16_SFLOAT],
+            ),
+        };
+
+        hdr_format
+            .or_else(|| self.find_surface_format(ColorSpace::SrgbNonLinear, &[Format::B8G8R8A8_SRGB, Format::R8G8B8A8_SRGB]))
+            // Any sRGB encoded format still gets gamma right
+            .or_else(|| self.surface_formats
+                .iter()
+                .find(|(format, color_space)| {
+                    *color_space == ColorSpace::SrgbNonLinear && format.numeric_format_color() == Some(NumericFormat::SRGB)
+                })
+                .copied())
+            .unwrap_or(self.surface_formats[0])
+    }
+
+    /// The first of `formats` supported in `color_space`
+    fn find_surface_format(&self, color_space: ColorSpace, formats: &[Format]) -> Option<(Format, ColorSpace)> {
+        formats
+            .iter()
+            .map(|&format| (format, color_space))
+            .find(|surface_format| self.surface_formats.contains(surface_format))
+    }
+
+    /// Every present mode the surface supports
+    pub fn present_modes(&self) -> &[PresentMode] {
+        &self.present_modes
+    }
+
+    /// The most preferred present mode for `vsync` the surface supports
+    pub fn present_mode(&self, vsync: Vsync) -> PresentMode {
+        vsync.present_modes()
+            .iter()
+            .copied()
+            .find(|present_mode| self.present_modes.contains(present_mode))
+            // FIFO support is guaranteed
+            .unwrap_or(PresentMode::Fifo)
+    }
+
+    /// The highest supported sample count that doesn't exceed `msaa`
+    pub fn clamp_samples(&self, msaa: Msaa) -> SampleCount {
+        [SampleCount::Sample8, SampleCount::Sample4, SampleCount::Sample2]
+            .into_iter()
+            .filter(|&samples| samples as u32 <= msaa.sample_count() as u32)
+            .find(|&samples| self.sample_counts.contains_enum(samples))
+            .unwrap_or(SampleCount::Sample1)
     }
 }
\ No newline at end of file
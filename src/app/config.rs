@@ -1,9 +1,41 @@
+use crate::app::capabilities::DeviceRequirements;
+use crate::app::overrides::{OverrideError, Overrides};
+use crate::app::resources::RenderPassConfig;
 use crate::app::settings::Settings;
+use crate::app::settings_file::{SettingsError, SettingsFile};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    SettingsError(#[from] SettingsError),
+    #[error("invalid override! {0}")]
+    OverrideError(#[from] OverrideError),
+}
 
 /// App config is designed to be used to construct the AppManager
 pub struct Config {
     pub app_name: String,
     pub settings: Settings,
+    pub render_pass: RenderPassConfig,
+    pub device_requirements: DeviceRequirements,
+    /// Windows opened alongside the primary one, which is titled `app_name` and sized by the settings
+    pub windows: Vec<WindowConfig>,
+    /// Where `settings` came from, the final settings are written back here on exit
+    pub settings_file: Option<SettingsFile>,
+    /// Creates every window hidden, for running on a machine nobody is watching. A display is still
+    /// needed, windows are created and presented to as usual
+    pub hidden_windows: bool,
+    /// Forced over the settings when the app starts. When `None` the `ICARUS_*` environment variables
+    /// are read then, so they work however the config was made
+    pub overrides: Option<Overrides>,
+}
+
+/// An extra window sharing the primary window's device
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    pub size: [u32; 2],
 }
 
 impl Default for Config {
@@ -11,6 +43,48 @@ impl Default for Config {
         Config {
             app_name: String::from("Icarus Engine"),
             settings: Settings::default(),
+            render_pass: RenderPassConfig::default(),
+            device_requirements: DeviceRequirements::default(),
+            windows: Vec::new(),
+            settings_file: None,
+            hidden_windows: false,
+            overrides: None,
         }
     }
+}
+
+impl Config {
+    /// A config with settings read from `app_name`'s settings file in the platform config directory
+    pub fn load(app_name: &str) -> Result<Self, SettingsError> {
+        let (settings_file, settings) = SettingsFile::load(SettingsFile::default_path(app_name)?)?;
+
+        Ok(Config {
+            app_name: app_name.to_string(),
+            settings,
+            settings_file: Some(settings_file),
+            ..Config::default()
+        })
+    }
+
+    /// Like `load`, with command line flags and `ICARUS_*` environment variables layered over the file
+    pub fn from_env(app_name: &str) -> Result<Self, ConfigError> {
+        Ok(Self::with_overrides(app_name, Overrides::from_env()?)?)
+    }
+
+    /// Like `from_env` with overrides the game already read, for example to build its logger at `Overrides::max_log_level`
+    pub fn with_overrides(app_name: &str, overrides: Overrides) -> Result<Self, SettingsError> {
+        let settings_path = match &overrides.settings_path {
+            Some(settings_path) => settings_path.value.clone(),
+            None => SettingsFile::default_path(app_name)?,
+        };
+        let (settings_file, settings) = SettingsFile::load(settings_path)?;
+
+        Ok(Config {
+            app_name: app_name.to_string(),
+            settings,
+            settings_file: Some(settings_file),
+            overrides: Some(overrides),
+            ..Config::default()
+        })
+    }
 }
\ No newline at end of file
@@ -1,48 +1,106 @@
-use crate::app::config::Config;
+use crate::app::config::{Config, WindowConfig};
 use crate::app::game::GameHandler;
+use crate::app::overrides::{Override, OverrideError, Overrides};
 use crate::app::resources::{RenderResources, ResourceError};
 use crate::app::settings::Settings;
+use crate::app::settings_file::{SettingsError, SettingsFile};
+use crate::app::window::WindowManager;
 use crate::app::GameError;
-use log::{debug, error, info, warn};
+use log::{debug, error, info, trace, warn};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use vulkano::render_pass::RenderPass;
 use vulkano::Version;
 use winit::application::ApplicationHandler;
 use winit::dpi::LogicalSize;
-use winit::error::EventLoopError;
+use winit::error::{EventLoopError, OsError};
 use winit::event::WindowEvent;
-use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::window::{Window, WindowId};
 
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("window event loop error! {0}")]
     WindowEventError(#[from] EventLoopError),
+    #[error("failed to create window! {0}")]
+    WindowCreationError(#[from] OsError),
     #[error(transparent)]
     ResourceError(#[from] ResourceError),
+    #[error(transparent)]
+    SettingsError(#[from] SettingsError),
+    #[error("invalid override! {0}")]
+    OverrideError(#[from] OverrideError),
     #[error("Game error! {0}")]
     GameError(#[from] GameError)
 }
 
+/// How the app responds to an error
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorSeverity {
+    /// The frame is skipped and tried again
+    Recoverable,
+    /// The device is recreated and the game's assets restored
+    DeviceLost,
+    /// The game is told through `GameHandler::on_error` and `AppManager::run_game` returns the error
+    Fatal,
+}
+
+impl AppError {
+    pub fn severity(&self) -> ErrorSeverity {
+        match self {
+            AppError::ResourceError(e) | AppError::GameError(GameError::ResourceError(e)) => {
+                if e.is_device_lost() {
+                    ErrorSeverity::DeviceLost
+                } else if e.is_recoverable() {
+                    ErrorSeverity::Recoverable
+                } else {
+                    ErrorSeverity::Fatal
+                }
+            }
+            _ => ErrorSeverity::Fatal,
+        }
+    }
+}
+
 pub struct AppManager {
     app_name: String,
     event_loop: EventLoop<()>,
     render_resources: RenderResources,
     settings: Settings,
+    windows: Vec<WindowConfig>,
+    settings_file: Option<SettingsFile>,
+    hidden_windows: bool,
 }
 
 impl AppManager {
-    pub fn from_config(config: Config) -> Result<Self, AppError> {
+    pub fn from_config(mut config: Config) -> Result<Self, AppError> {
+        let overrides = match config.overrides.take() {
+            Some(overrides) => overrides,
+            None => Overrides::from_vars()?,
+        };
+
+        // Taken before the override replaces it, so the device is logged with where it was asked for
+        let preferred_device = overrides.device.clone().or_else(|| {
+            config.settings.preferred_device.map(|value| Override { value, source: String::from("the settings") })
+        });
+        overrides.apply(&mut config);
+
+        info!("Starting {} with {:?}", config.app_name, config.settings);
+
         let event_loop = EventLoop::new()?;
 
-        let render_resources = RenderResources::create(&event_loop, Some(config.app_name.clone()), Version::default())?;
+        let render_resources = RenderResources::create(&event_loop, Some(config.app_name.clone()), Version::default(), config.render_pass, config.settings.clone(), preferred_device, config.device_requirements)?;
 
         Ok(Self {
             app_name: config.app_name,
             event_loop,
             render_resources,
             settings: config.settings,
+            windows: config.windows,
+            settings_file: config.settings_file,
+            hidden_windows: config.hidden_windows,
         })
     }
 
@@ -52,13 +110,28 @@ impl AppManager {
         let mut handler = AppHandler {
             app_name: self.app_name,
             render_resources: self.render_resources,
-            settings: self.settings,
-            game
+            window_configs: self.windows,
+            extra_windows: Vec::new(),
+            closed_windows: HashSet::new(),
+            windows: WindowManager::new(self.settings.window),
+            settings_file: self.settings_file,
+            hidden_windows: self.hidden_windows,
+            game,
+            device_lost: false,
+            fatal_error: None,
+            minimized: HashSet::new(),
+            occluded: HashSet::new(),
+            redraw_requested: HashSet::new(),
+            focused: HashSet::new(),
+            last_frame: None,
         };
 
         self.event_loop.run_app(&mut handler)?;
 
-        Ok(())
+        match handler.fatal_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 }
 
@@ -66,41 +139,254 @@ impl AppManager {
 struct AppHandler<'a, Game: GameHandler> {
     app_name: String,
     render_resources: RenderResources,
-    settings: Settings,
-    game: &'a mut Game
+    window_configs: Vec<WindowConfig>,
+    // The open window for each index of `window_configs`
+    extra_windows: Vec<(WindowId, usize)>,
+    // Indices of `window_configs` the user closed, so resuming doesn't open them again
+    closed_windows: HashSet<usize>,
+    windows: WindowManager,
+    settings_file: Option<SettingsFile>,
+    hidden_windows: bool,
+    game: &'a mut Game,
+    // Set while the game holds nothing usable on the GPU, until the device is restored
+    device_lost: bool,
+    // The error that stopped the app, returned from `run_game`
+    fatal_error: Option<AppError>,
+    // Windows shrunk to nothing, which have no swapchain to draw with until restored
+    minimized: HashSet<WindowId>,
+    // Windows the platform says are hidden, so there's nothing to see if they were drawn
+    occluded: HashSet<WindowId>,
+    // Windows `update` asked to redraw that haven't yet, the rest of their redraws come from the platform
+    redraw_requested: HashSet<WindowId>,
+    focused: HashSet<WindowId>,
+    last_frame: Option<Instant>,
 }
 
 impl <T: GameHandler> AppHandler<'_, T> {
     
-    fn draw(&mut self) -> Result<(), AppError> {
+    /// Runs the game for a frame and asks every window that isn't paused to redraw
+    fn update(&mut self) -> Result<(), AppError> {
+        let windows: Vec<_> = self.render_resources
+            .windows()
+            .into_iter()
+            .filter(|&window_id| !self.is_paused(window_id))
+            .collect();
+
+        // Everything is minimized, so there's no point simulating either
+        if windows.is_empty() {
+            return Ok(());
+        }
+
+        self.last_frame = Some(Instant::now());
+
+        self.game.update(&mut self.windows)?;
+
         // Let there be fish in the sea of love
-        self.game.draw(&mut self.render_resources)?;
+        self.game.compute(&mut self.render_resources)?;
+
+        for window_id in windows {
+            if let Some(window) = self.windows.get(window_id) {
+                window.request_redraw();
+                self.redraw_requested.insert(window_id);
+            }
+        }
 
         Ok(())
     }
-}
 
-impl <T: GameHandler> ApplicationHandler for AppHandler<'_, T> {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes()
-            .with_title(self.app_name.clone())
-            .with_inner_size(LogicalSize::new(self.settings.window_size[0], self.settings.window_size[1]));
-        let window = match event_loop.create_window(window_attributes) {
-            Ok(window) => Arc::new(window),
-            Err(e) => {
-                error!("Failed to create Window! {e}");
-                event_loop.exit();
-                return;
+    /// Whether a window is minimized or hidden, so it isn't updated or drawn
+    fn is_paused(&self, window_id: WindowId) -> bool {
+        self.minimized.contains(&window_id) || self.occluded.contains(&window_id)
+    }
+
+    /// Whether a redraw the platform asked for should wait for the next frame, as `update` was throttled
+    fn is_redraw_throttled(&self, window_id: WindowId) -> bool {
+        !self.redraw_requested.contains(&window_id)
+            && self.throttled_until().is_some_and(|next_frame| Instant::now() < next_frame)
+    }
+
+    /// Draws a window. One window being minimized shouldn't stop the others drawing, so recoverable errors only skip it
+    fn draw(&mut self, window_id: WindowId) -> Result<(), AppError> {
+        if let Err(e) = self.game.draw(window_id, &mut self.render_resources) {
+            let e = AppError::from(e);
+            if e.severity() != ErrorSeverity::Recoverable {
+                return Err(e);
             }
-        };
+            trace!("Skipping window {window_id:?}, {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Replaces a lost device and lets the game restore itself onto the new one.
+    /// Windows the new device can't present to are closed
+    fn recover_device(&mut self) -> Result<(), AppError> {
+        self.game.on_device_lost();
+
+        for window_id in self.render_resources.recreate_device_resources()? {
+            self.forget_window(window_id);
+        }
+
+        self.render_resources.create_swapchains()?;
+        self.restore_assets()
+    }
+
+    /// Skips the frame on recoverable errors and recreates a lost device, anything else stops the app
+    fn handle_error(&mut self, event_loop: &ActiveEventLoop, error: AppError) {
+        match error.severity() {
+            ErrorSeverity::Recoverable => trace!("Skipping frame, {error}"),
+            ErrorSeverity::DeviceLost => {
+                warn!("Device lost, recreating it! {error}");
+                // Losing the device again while recovering isn't worth retrying
+                if let Err(e) = self.recover_device() {
+                    self.fail(event_loop, e);
+                }
+            }
+            ErrorSeverity::Fatal => self.fail(event_loop, error),
+        }
+    }
 
-        debug!("Created a new window!");
+    fn fail(&mut self, event_loop: &ActiveEventLoop, error: AppError) {
+        error!("{error}");
+        self.game.on_error(&error);
+
+        // Keep the first error, anything after is likely fallout from it
+        self.fatal_error.get_or_insert(error);
+        event_loop.exit();
+    }
 
-        if let Err(e) = self.render_resources.create_device_resources(window.clone()) {
-            error!("Failed to recreate Active Resources! {e}");
+    /// Opens the primary window and the configured extra windows, creating the device for the primary one
+    fn create_windows(&mut self, event_loop: &ActiveEventLoop) -> Result<(), AppError> {
+        self.windows.clear();
+
+        let primary_attributes = self.windows
+            .primary_attributes(event_loop)
+            .with_title(self.app_name.clone());
+        let extra_attributes = self.window_configs
+            .iter()
+            .map(|window_config| Window::default_attributes()
+                .with_title(window_config.title.clone())
+                .with_inner_size(LogicalSize::new(window_config.size[0], window_config.size[1])));
+
+        let windows = [primary_attributes]
+            .into_iter()
+            .chain(extra_attributes)
+            .enumerate()
+            .filter(|(index, _)| !self.closed_windows.contains(index));
+
+        for (index, window_attributes) in windows {
+            let window = Arc::new(event_loop.create_window(window_attributes.with_visible(!self.hidden_windows))?);
+
+            if index == 0 {
+                self.render_resources.create_device_resources(window.clone())?;
+            } else {
+                self.render_resources.add_window(window.clone())?;
+                self.extra_windows.push((window.id(), index));
+            }
+
+            debug!("Created window {:?}", window.id());
+            self.windows.add(window.clone());
+            self.game.on_window_created(window.id(), index);
+        }
+
+        Ok(())
+    }
+
+    /// Closes a window, exiting when it's the primary one. Does nothing for a window that is already gone
+    fn close_window(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId) -> Result<(), AppError> {
+        if self.windows.get(window_id).is_none() {
+            return Ok(());
+        }
+
+        if self.windows.is_primary(window_id) {
+            info!("Primary window closed, exiting");
             event_loop.exit();
+        } else {
+            self.render_resources.remove_window(window_id)?;
+            self.forget_window(window_id);
+        }
+
+        Ok(())
+    }
+
+    /// Drops a window the render resources no longer present to, which closes it, and tells the game
+    fn forget_window(&mut self, window_id: WindowId) {
+        self.windows.remove(window_id);
+        self.minimized.remove(&window_id);
+        self.occluded.remove(&window_id);
+        self.redraw_requested.remove(&window_id);
+        self.focused.remove(&window_id);
+
+        if let Some(position) = self.extra_windows.iter().position(|(id, _)| *id == window_id) {
+            let (_, index) = self.extra_windows.swap_remove(position);
+            self.closed_windows.insert(index);
+        }
+
+        self.game.on_window_closed(window_id);
+    }
+
+    /// Writes the settings as they are now, including the game's sections, back to the settings file
+    fn save_settings(&mut self) -> Result<(), AppError> {
+        let Some(settings_file) = &mut self.settings_file else {
+            return Ok(());
+        };
+
+        let mut settings = self.render_resources.settings().clone();
+        settings.window = self.windows.settings().clone();
+
+        self.game.save_settings(settings_file)?;
+        settings_file.save(&settings)?;
+
+        Ok(())
+    }
+
+    /// When the next frame is due if drawing is throttled by the frame rate cap, or because no window has focus
+    fn throttled_until(&self) -> Option<Instant> {
+        let unfocused_interval = self.render_resources
+            .settings()
+            .unfocused_frame_rate
+            .filter(|&rate| rate > 0 && self.focused.is_empty())
+            .map(|rate| Duration::from_secs_f64(1.0 / rate as f64));
+
+        // Whichever limit is slower wins
+        let interval = self.render_resources.frame_interval().into_iter().chain(unfocused_interval).max()?;
+
+        Some(self.last_frame? + interval)
+    }
+
+    /// Restores the game's GPU state after device resources were created again on resume
+    fn restore_device(&mut self) -> Result<(), AppError> {
+        if self.device_lost {
+            self.restore_assets()?;
+            self.device_lost = false;
+        }
+
+        Ok(())
+    }
+
+    /// Restores every live asset onto the new device and tells the game how many couldn't be
+    fn restore_assets(&mut self) -> Result<(), AppError> {
+        let failed_assets = self.render_resources.restore_assets()?;
+        if failed_assets > 0 {
+            warn!("{failed_assets} assets could not be restored on the new device");
+        }
+
+        self.game.on_device_restored(&mut self.render_resources, failed_assets)?;
+
+        Ok(())
+    }
+}
+
+impl <T: GameHandler> ApplicationHandler for AppHandler<'_, T> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if let Err(e) = self.create_windows(event_loop) {
+            self.fail(event_loop, e);
             return;
         }
+
+        if let Err(e) = self.restore_device() {
+            self.handle_error(event_loop, e);
+        }
     }
 
     fn window_event(
@@ -109,36 +395,104 @@ impl <T: GameHandler> ApplicationHandler for AppHandler<'_, T> {
         window_id: WindowId,
         event: WindowEvent,
     ) {
+        // Events can still arrive for a window that was just closed
+        if self.windows.get(window_id).is_none() {
+            return;
+        }
+
         match event {
             WindowEvent::CloseRequested => {
-                info!("Received Close Window Event");
-                event_loop.exit();
+                info!("Received Close Window Event for {window_id:?}");
+                if let Err(e) = self.close_window(event_loop, window_id) {
+                    self.handle_error(event_loop, e);
+                }
+            }
+            WindowEvent::RedrawRequested if !self.device_lost && !self.is_paused(window_id) && !self.is_redraw_throttled(window_id) => {
+                self.redraw_requested.remove(&window_id);
+                if let Err(e) = self.draw(window_id) {
+                    self.handle_error(event_loop, e);
+                }
+            }
+            WindowEvent::Resized(size) => {
+                self.windows.on_resized(window_id, size);
+
+                // Minimizing shrinks the window to nothing, its swapchain waits until it comes back
+                if size.width == 0 || size.height == 0 {
+                    debug!("Window {window_id:?} minimized, pausing it");
+                    self.minimized.insert(window_id);
+                    return;
+                }
+
+                if self.minimized.remove(&window_id) {
+                    debug!("Window {window_id:?} restored, resuming it");
+                }
+
+                if let Err(e) = self.render_resources.recreate_swapchain(window_id) {
+                    self.handle_error(event_loop, e.into());
+                }
             }
-            WindowEvent::RedrawRequested => {
-                if let Err(e) = self.draw() {
-                    error!("Failed to draw on redraw request: {}", e);
-                    event_loop.exit();
+            WindowEvent::Occluded(occluded) => {
+                if occluded {
+                    self.occluded.insert(window_id);
+                } else {
+                    self.occluded.remove(&window_id);
                 }
             }
-            WindowEvent::Resized(_) => {
-                if let Err(e) = &mut self.render_resources.recreate_swapchain() {
-                    error!("Failed to recreate swapchain! {e}");
-                    event_loop.exit();
+            WindowEvent::Focused(focused) => {
+                if focused {
+                    self.focused.insert(window_id);
+                } else {
+                    self.focused.remove(&window_id);
                 }
             }
+            WindowEvent::Moved(position) => {
+                self.windows.on_moved(window_id, position);
+            }
             _ => (),
         }
     }
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
-        if let Err(e) = self.draw() {
-            error!("Failed to draw when waiting: {}", e);
-            event_loop.exit();
+        // Nothing to draw with until we resume
+        if self.device_lost {
+            return;
+        }
+
+        // Sleep until a window is restored rather than spinning with nothing to show
+        let windows = self.render_resources.windows();
+        if windows.iter().all(|&window_id| self.is_paused(window_id)) {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+
+        if let Some(next_frame) = self.throttled_until().filter(|&next_frame| Instant::now() < next_frame) {
+            event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame));
+            return;
+        }
+
+        event_loop.set_control_flow(ControlFlow::Poll);
+
+        if let Err(e) = self.update() {
+            self.handle_error(event_loop, e);
         }
     }
 
     fn suspended(&mut self, event_loop: &ActiveEventLoop) {
         self.render_resources.destroy_device_resources();
+        self.windows.clear();
+        self.extra_windows.clear();
+        self.minimized.clear();
+        self.occluded.clear();
+        self.redraw_requested.clear();
+        self.focused.clear();
+        self.game.on_device_lost();
+        self.device_lost = true;
         debug!("App resources nuked!");
     }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Err(e) = self.save_settings() {
+            error!("Failed to save settings! {e}");
+        }
+    }
 }
\ No newline at end of file
@@ -1,14 +1,51 @@
+use crate::app::core::AppError;
 use crate::app::resources;
 use crate::app::resources::RenderResources;
+use crate::app::settings_file::{SettingsError, SettingsFile};
+use crate::app::window::{WindowError, WindowManager};
 use thiserror::Error;
+use winit::window::WindowId;
 
 #[derive(Error, Debug)]
 pub enum GameError {
     #[error(transparent)]
     ResourceError(#[from] resources::ResourceError),
+    #[error(transparent)]
+    WindowError(#[from] WindowError),
+    #[error(transparent)]
+    SettingsError(#[from] SettingsError),
 }
 
 pub trait GameHandler {
     fn on_start(&mut self);
-    fn draw(&mut self, resources: &mut RenderResources) -> Result<(), GameError>;
+    /// A window was opened, `index` 0 is the primary window and the rest follow `Config::windows`
+    fn on_window_created(&mut self, _window: WindowId, _index: usize) {}
+    /// Called once a frame before anything is drawn, `windows` changes the window at runtime
+    fn update(&mut self, _windows: &mut WindowManager) -> Result<(), GameError> {
+        Ok(())
+    }
+    /// Dispatches GPU simulation work, called once before the windows are drawn
+    fn compute(&mut self, _resources: &mut RenderResources) -> Result<(), GameError> {
+        Ok(())
+    }
+    /// Draws one window, called when it redraws after `update` and `compute` ran for the frame
+    fn draw(&mut self, window: WindowId, resources: &mut RenderResources) -> Result<(), GameError>;
+    /// A window other than the primary one was closed by the user, or dropped because a new device
+    /// can't present to it. It has already been removed
+    fn on_window_closed(&mut self, _window: WindowId) {}
+    /// The device is gone, from suspending or a driver failure. Nothing on the GPU can be used until
+    /// `on_device_restored`
+    fn on_device_lost(&mut self) {}
+    /// A new device is ready, every live `ModelData` and `Texture` was restored onto it except
+    /// `failed_assets`, which keep their old resources and should be reloaded. `AssetServer`s already
+    /// load onto the new device, anything else on the GPU, such as storage buffers, must be recreated here
+    fn on_device_restored(&mut self, _resources: &mut RenderResources, _failed_assets: usize) -> Result<(), GameError> {
+        Ok(())
+    }
+    /// Something went wrong that the app can't recover from, it exits and `AppManager::run_game` returns `error`
+    fn on_error(&mut self, _error: &AppError) {}
+    /// The app is exiting and `settings` is about to be saved, store the game's own sections with `set_section`
+    fn save_settings(&mut self, _settings: &mut SettingsFile) -> Result<(), GameError> {
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,294 @@
+use crate::app::capabilities::parse_device_id;
+use crate::app::config::Config;
+use log::{debug, info, LevelFilter};
+use std::{env, iter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use thiserror::Error;
+
+const ENV_PREFIX: &str = "ICARUS_";
+
+#[derive(Error, Debug)]
+pub enum OverrideError {
+    #[error("{name} expects {expected}, got {value:?}")]
+    InvalidValue {
+        name: String,
+        value: String,
+        expected: &'static str,
+    },
+    #[error("{0} expects a value")]
+    MissingValue(String),
+}
+
+/// A value and the flag or environment variable it came from
+#[derive(Clone, Debug)]
+pub struct Override<T> {
+    pub value: T,
+    pub source: String,
+}
+
+/// Settings forced from the command line or `ICARUS_*` environment variables, for testing without recompiling.
+/// Flags win over environment variables, which win over the settings file
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    /// `--window-size WxH` or `ICARUS_WINDOW_SIZE`
+    pub window_size: Option<Override<[u32; 2]>>,
+    /// `--device vendor:device` or `ICARUS_DEVICE`, hex ids as `DeviceInfo` prints them
+    pub device: Option<Override<(u32, u32)>>,
+    /// `--log-level level` or `ICARUS_LOG_LEVEL`, see `max_log_level`
+    pub log_level: Option<Override<LevelFilter>>,
+    /// `--hidden-windows` or `ICARUS_HIDDEN_WINDOWS=1`, windows are created hidden but still need a display
+    pub hidden_windows: Option<Override<bool>>,
+    /// `--settings path` or `ICARUS_SETTINGS`, used instead of the settings file in the config directory
+    pub settings_path: Option<Override<PathBuf>>,
+}
+
+impl Overrides {
+    /// Reads the process's arguments and environment
+    pub fn from_env() -> Result<Self, OverrideError> {
+        Self::parse(env::args().skip(1), env::vars())
+    }
+
+    /// Reads only the `ICARUS_*` environment variables, for configs not made by `Config::from_env`
+    pub fn from_vars() -> Result<Self, OverrideError> {
+        Self::parse(iter::empty(), env::vars())
+    }
+
+    /// The forced log level. `apply` only sets `log::set_max_level`, which can't raise a logger's own
+    /// filter, so read the overrides first and build the game's logger with this level, for example
+    /// `env_logger::Builder::new().filter_level(level)`, before passing them to `Config::with_overrides`
+    pub fn max_log_level(&self) -> Option<LevelFilter> {
+        self.log_level.as_ref().map(|log_level| log_level.value)
+    }
+
+    /// Parses `args`, excluding the program name, over `vars`. Arguments that aren't ours are left to the game
+    pub fn parse<A, V>(args: A, vars: V) -> Result<Self, OverrideError>
+    where
+        A: IntoIterator<Item = String>,
+        V: IntoIterator<Item = (String, String)>,
+    {
+        let mut overrides = Overrides::default();
+
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+
+            match key {
+                "WINDOW_SIZE" => overrides.window_size = Some(parse_value(name, value)?),
+                "DEVICE" => overrides.device = Some(parse_value(name, value)?),
+                "LOG_LEVEL" => overrides.log_level = Some(parse_value(name, value)?),
+                "HIDDEN_WINDOWS" => overrides.hidden_windows = Some(parse_value(name, value)?),
+                "SETTINGS" => overrides.settings_path = Some(parse_value(name, value)?),
+                _ => {}
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+
+            if flag == "--hidden-windows" {
+                let value = inline_value.unwrap_or_else(|| String::from("true"));
+                overrides.hidden_windows = Some(parse_value(flag, value)?);
+                continue;
+            }
+
+            let is_ours = matches!(flag.as_str(), "--window-size" | "--device" | "--log-level" | "--settings");
+            if !is_ours {
+                debug!("Ignoring argument {flag}");
+                continue;
+            }
+
+            let value = inline_value
+                .or_else(|| args.next())
+                .ok_or_else(|| OverrideError::MissingValue(flag.clone()))?;
+
+            match flag.as_str() {
+                "--window-size" => overrides.window_size = Some(parse_value(flag, value)?),
+                "--device" => overrides.device = Some(parse_value(flag, value)?),
+                "--log-level" => overrides.log_level = Some(parse_value(flag, value)?),
+                "--settings" => overrides.settings_path = Some(parse_value(flag, value)?),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Applies the overrides to `config`, logging each one
+    pub fn apply(&self, config: &mut Config) {
+        // First, so the rest of the startup is logged at the requested level
+        if let Some(log_level) = &self.log_level {
+            log::set_max_level(log_level.value);
+            info!("Log level {} from {}", log_level.value, log_level.source);
+        }
+
+        if let Some(window_size) = &self.window_size {
+            config.settings.window.size = window_size.value;
+            info!("Window size {}x{} from {}", window_size.value[0], window_size.value[1], window_size.source);
+        }
+
+        if let Some(device) = &self.device {
+            config.settings.preferred_device = Some(device.value);
+            info!("Preferred device {:04x}:{:04x} from {}", device.value.0, device.value.1, device.source);
+        }
+
+        if let Some(hidden_windows) = &self.hidden_windows {
+            config.hidden_windows = hidden_windows.value;
+            info!("Hidden windows {} from {}", hidden_windows.value, hidden_windows.source);
+        }
+
+        if let Some(settings_path) = &self.settings_path {
+            info!("Settings file {} from {}", settings_path.value.display(), settings_path.source);
+        }
+
+        // A test run with a forced size or device shouldn't change the player's settings
+        let overrides_settings = self.window_size.is_some() || self.device.is_some();
+        if let Some(settings_file) = config.settings_file.as_mut().filter(|_| overrides_settings) {
+            info!("Settings are overridden, changes won't be saved to {}", settings_file.path().display());
+            settings_file.set_read_only(true);
+        }
+    }
+}
+
+/// A value that can be given as a flag or environment variable
+trait OverrideValue: Sized {
+    const EXPECTED: &'static str;
+
+    fn parse(value: &str) -> Option<Self>;
+}
+
+impl OverrideValue for [u32; 2] {
+    const EXPECTED: &'static str = "a size such as 1280x720";
+
+    fn parse(value: &str) -> Option<Self> {
+        let (width, height) = value.trim().split_once(['x', 'X'])?;
+        let size = [width.parse().ok()?, height.parse().ok()?];
+
+        (!size.contains(&0)).then_some(size)
+    }
+}
+
+impl OverrideValue for (u32, u32) {
+    const EXPECTED: &'static str = "a vendor:device pair of hex ids";
+
+    fn parse(value: &str) -> Option<Self> {
+        parse_device_id(value)
+    }
+}
+
+impl OverrideValue for LevelFilter {
+    const EXPECTED: &'static str = "one of off, error, warn, info, debug or trace";
+
+    fn parse(value: &str) -> Option<Self> {
+        LevelFilter::from_str(value.trim()).ok()
+    }
+}
+
+impl OverrideValue for bool {
+    const EXPECTED: &'static str = "true, false, 1 or 0";
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(true),
+            "0" | "false" | "no" | "off" | "" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl OverrideValue for PathBuf {
+    const EXPECTED: &'static str = "a path";
+
+    fn parse(value: &str) -> Option<Self> {
+        (!value.is_empty()).then(|| PathBuf::from(value))
+    }
+}
+
+fn parse_value<T: OverrideValue>(name: String, value: String) -> Result<Override<T>, OverrideError> {
+    match T::parse(&value) {
+        Some(parsed) => Ok(Override { value: parsed, source: name }),
+        None => Err(OverrideError::InvalidValue { name, value, expected: T::EXPECTED }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_inline_and_separate_values() {
+        let overrides = Overrides::parse(args(&["--window-size=1280x720", "--device", "10de:2684"]), vars(&[])).unwrap();
+
+        let window_size = overrides.window_size.unwrap();
+        assert_eq!(window_size.value, [1280, 720]);
+        assert_eq!(window_size.source, "--window-size");
+
+        let device = overrides.device.unwrap();
+        assert_eq!(device.value, (0x10de, 0x2684));
+        assert_eq!(device.source, "--device");
+    }
+
+    #[test]
+    fn flags_win_over_env_vars() {
+        let overrides = Overrides::parse(
+            args(&["--log-level", "debug"]),
+            vars(&[("ICARUS_LOG_LEVEL", "warn"), ("ICARUS_HIDDEN_WINDOWS", "1")]),
+        ).unwrap();
+
+        let log_level = overrides.log_level.unwrap();
+        assert_eq!(log_level.value, LevelFilter::Debug);
+        assert_eq!(log_level.source, "--log-level");
+
+        let hidden_windows = overrides.hidden_windows.unwrap();
+        assert!(hidden_windows.value);
+        assert_eq!(hidden_windows.source, "ICARUS_HIDDEN_WINDOWS");
+    }
+
+    #[test]
+    fn ignores_other_arguments_and_vars() {
+        let overrides = Overrides::parse(args(&["--fullscreen", "level1"]), vars(&[("HOME", "/root"), ("ICARUS_OTHER", "1")])).unwrap();
+
+        assert!(overrides.window_size.is_none());
+        assert!(overrides.device.is_none());
+        assert!(overrides.log_level.is_none());
+        assert!(overrides.hidden_windows.is_none());
+        assert!(overrides.settings_path.is_none());
+    }
+
+    #[test]
+    fn missing_value_is_an_error() {
+        let error = Overrides::parse(args(&["--settings"]), vars(&[])).unwrap_err();
+
+        assert!(matches!(error, OverrideError::MissingValue(flag) if flag == "--settings"));
+    }
+
+    #[test]
+    fn bad_size_is_an_error() {
+        for size in ["1280", "1280x", "0x720", "widexhigh"] {
+            let error = Overrides::parse(args(&["--window-size", size]), vars(&[])).unwrap_err();
+
+            assert!(matches!(error, OverrideError::InvalidValue { name, value, .. } if name == "--window-size" && value == size));
+        }
+    }
+
+    #[test]
+    fn bad_device_id_is_an_error() {
+        let error = Overrides::parse(args(&[]), vars(&[("ICARUS_DEVICE", "nvidia")])).unwrap_err();
+
+        assert!(matches!(error, OverrideError::InvalidValue { name, .. } if name == "ICARUS_DEVICE"));
+    }
+}
@@ -1,30 +1,50 @@
-use crate::app::capabilities::{Capabilities, CapabilityError};
-use crate::app::resources::utils::{get_debug_utils_callback, get_required_layers, is_required_layer_support_available, REQUIRED_INSTANCE_EXTENSIONS};
-use log::{debug, trace, warn};
+use crate::app::capabilities::{Capabilities, CapabilityError, DeviceInfo, DeviceRequirements};
+use crate::app::overrides::Override;
+use crate::app::resources::utils::{get_debug_utils_callback, get_required_layers, is_required_layer_support_available, OPTIONAL_INSTANCE_EXTENSIONS, REQUIRED_INSTANCE_EXTENSIONS};
+use crate::app::settings::{DisplayColorSpace, Msaa, PostEffect, Settings, UpscaleFilter, Vsync};
+use crate::app::shaders::IcarusShader;
+use crate::assets::{AssetContext, AssetTracker};
+use crate::ecs::core::components::{Material, ModelData};
+use crate::render::{CommandBuilder, RenderGraphError};
+use log::{debug, error, info, trace, warn};
+use std::iter;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use vulkano::buffer::Subbuffer;
+use vulkano::buffer::{AllocateBufferError, Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
 use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents, SubpassEndInfo};
-use vulkano::device::{Device, DeviceCreateInfo, DeviceOwned, Queue, QueueCreateInfo, QueueFlags};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferExecError, CommandBufferUsage};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, DeviceFeatures, Queue, QueueCreateInfo, QueueFlags};
 use vulkano::format::Format;
-use vulkano::image::view::{ImageView, ImageViewCreateInfo, ImageViewType};
-use vulkano::image::{Image, ImageAspects, ImageSubresourceRange, ImageUsage};
+use vulkano::image::{Image, ImageUsage, SampleCount};
 use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessengerCreateInfo};
 use vulkano::instance::{Instance, InstanceCreateInfo};
-use vulkano::pipeline::graphics::vertex_input::VertexBuffersCollection;
-use vulkano::pipeline::GraphicsPipeline;
-use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
-use vulkano::swapchain::{AcquireNextImageInfo, AcquiredImage, FromWindowError, Surface, Swapchain, SwapchainCreateInfo};
-use vulkano::{sync, LoadingError, Validated, ValidationError, Version, VulkanError, VulkanLibrary};
-use vulkano::sync::future::FenceSignalFuture;
-use vulkano::sync::GpuFuture;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::swapchain::{ColorSpace, FromWindowError, PresentMode, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo};
+use vulkano::sync::future::SemaphoreSignalFuture;
+use vulkano::sync::{GpuFuture, Sharing};
+use vulkano::{swapchain, sync, LoadingError, Validated, ValidationError, Version, VulkanError, VulkanLibrary};
 use winit::event_loop::EventLoop;
 use winit::raw_window_handle::HandleError;
-use winit::window::Window;
-
+use winit::window::{Window, WindowId};
+
+mod compute;
+mod frame_graph;
+mod memory;
+mod pipelines;
+mod post_process;
+mod render_pass;
 mod utils;
 
+use compute::ComputePipelineCache;
+use frame_graph::{FrameData, FrameGraph, GraphContext};
+pub use memory::{HeapStats, TrackingMemoryAllocator};
+pub use pipelines::*;
+pub use render_pass::RenderPassConfig;
+
 #[derive(Error, Debug)]
 pub enum ResourceError {
     #[error("failed to load Vulkan! {0}")]
@@ -47,121 +67,111 @@ pub enum ResourceError {
     GraphicsPipelineError(#[from] Box<ValidationError>),
     #[error("attempt to draw without required resources")]
     MissingRequiredResources,
+    #[error(transparent)]
+    PipelineError(#[from] PipelineError),
+    #[error("failed to submit command buffer! {0}")]
+    CommandBufferExecError(#[from] CommandBufferExecError),
+    #[error(transparent)]
+    RenderGraphError(#[from] RenderGraphError),
+    #[error("vulkan error! {0}")]
+    BufferAllocationError(#[from] Validated<AllocateBufferError>),
+    #[error("the device cannot present to this window")]
+    UnsupportedWindow,
+    #[error("no window with id {0:?}")]
+    UnknownWindow(WindowId),
+    #[error("window {0:?} has no area to draw to")]
+    ZeroSizedWindow(WindowId),
+    #[error("timed out acquiring a swapchain image")]
+    AcquireTimeout,
 }
 
-/// Wow, there's a lot of boilerplate
-struct DrawResources {
-    fences: Vec<Option<Arc<FenceSignalFuture<_>>>>
-}
-
-impl DrawResources {
-    pub fn new(device_resources: &mut DeviceResources) -> Result<Self, ResourceError> {
-        let mut swapchain_resources = device_resources.swapchain_resources
-            .as_mut()
-            .ok_or(ResourceError::MissingRequiredResources)?;
-        
-        let image_i = unsafe {
-            swapchain_resources.swapchain.acquire_next_image(&AcquireNextImageInfo {
-                timeout: None,
-                ..AcquireNextImageInfo::default()
-            })
-        }?;
+impl ResourceError {
+    /// Whether the device was lost, after which it has to be recreated
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self.vulkan_error(), Some(VulkanError::DeviceLost))
+    }
 
-        if image_i.is_suboptimal {
-            // Must recreate
-            trace!("Swapchain is suboptimal and must be recreated");
-            *swapchain_resources = swapchain_resources.recreate_identical()?;
+    /// Whether skipping the frame and trying again later is enough, such as when the swapchain is out of date
+    /// or the window is minimized
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            ResourceError::ZeroSizedWindow(_) | ResourceError::AcquireTimeout => true,
+            _ => matches!(self.vulkan_error(), Some(VulkanError::OutOfDate | VulkanError::Timeout | VulkanError::NotReady)),
         }
+    }
 
-        let execution = sync::now(swapchain_resources.device().clone())
-            .join(image_i)
-            .then_execute(swapchain_resources.swapchain.clone(), command_buffers[image_i as usize].clone())
-            .unwrap()
-            .then_swapchain_present(
-                queue.clone(),
-                SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_i),
-            )
-            .then_signal_fence_and_flush();
-
-        Self {
-            fences: vec![None; frames_in_flight]
-        }
+    /// The first Vulkan error in the source chain, however deeply it is wrapped in graph, pipeline or pass errors
+    fn vulkan_error(&self) -> Option<&VulkanError> {
+        iter::successors(Some(self as &(dyn std::error::Error + 'static)), |error| error.source())
+            .find_map(|error| match error.downcast_ref::<Validated<VulkanError>>() {
+                Some(Validated::Error(e)) => Some(e),
+                _ => error.downcast_ref::<VulkanError>(),
+            })
     }
 }
 
+/// How long to wait for a swapchain image before skipping the frame
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Flushed compute work. Its semaphore can only be waited on once, by the next frame submitted to the
+/// graphics queue, which orders every later submission on that queue after the compute too
+type ComputeFuture = SemaphoreSignalFuture<Box<dyn GpuFuture>>;
+
 /// Resources that may be destroyed any time
 struct SwapchainResources {
-    render_pass: Arc<RenderPass>,
+    graph_context: GraphContext,
+    render_pass_config: RenderPassConfig,
+    settings: Settings,
+    samples: SampleCount,
     swapchain: Arc<Swapchain>,
     images: Vec<Arc<Image>>,
-    frame_buffers: Vec<Arc<Framebuffer>>,
+    frame_graph: FrameGraph,
 
-    draw_resources: Option<DrawResources>
+    // The last submitted frame, so we don't get ahead of the GPU
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    // Set when the swapchain no longer matches the surface
+    recreate_pending: bool,
 }
 
 impl SwapchainResources {
-    pub fn new(active_resources: &DeviceResources) -> Result<Self, ResourceError> {
+    pub fn new(
+        active_resources: &DeviceResources,
+        window_resources: &WindowResources,
+        render_pass_config: RenderPassConfig,
+        settings: &Settings,
+    ) -> Result<Self, ResourceError> {
+        let (image_format, image_color_space) = window_resources.surface_format(settings.color_space);
+        let present_mode = window_resources.present_mode(settings.vsync);
+
         let (swapchain, images) = Swapchain::new(
             active_resources.device.clone(),
-            active_resources.vulkan_surface.clone(),
+            window_resources.vulkan_surface.clone(),
             SwapchainCreateInfo {
-                min_image_count: active_resources.capabilities.swapchain_images(),
-                image_format: active_resources.capabilities.image_format().0,
-                image_extent: active_resources.window.inner_size().into(),
-                image_usage: ImageUsage::COLOR_ATTACHMENT,
-                composite_alpha: *active_resources.capabilities.composite_alpha(),
+                min_image_count: window_resources.capabilities.swapchain_images(),
+                image_format,
+                image_color_space,
+                image_extent: window_resources.window.inner_size().into(),
+                // The scene is drawn offscreen and blitted in
+                image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST,
+                composite_alpha: *window_resources.capabilities.composite_alpha(),
+                present_mode,
                 ..SwapchainCreateInfo::default()
             },
         )?;
 
-        let render_pass = vulkano::single_pass_renderpass!(
-            active_resources.device.clone(),
-            attachments: {
-                color: {
-                    format: swapchain.image_format(),
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: Store,
-                },
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {},
-            },
-        )?;
+        let samples = active_resources.capabilities.clamp_samples(settings.msaa);
+        if samples != settings.msaa.sample_count() {
+            warn!("{:?} is not supported by this device, falling back to {samples:?}", settings.msaa);
+        }
 
-        let frame_buffers = images
-            .iter()
-            .cloned()
-            .map(|image| {
-                let create_info = ImageViewCreateInfo {
-                    view_type: ImageViewType::Dim2d,
-                    subresource_range: ImageSubresourceRange {
-                        aspects: ImageAspects::COLOR,
-                        array_layers: 0..1,
-                        mip_levels: 0..1,
-                    },
-                    ..ImageViewCreateInfo::from_image(&image)
-                };
-
-                let view = ImageView::new(image, create_info)?;
-
-                Framebuffer::new(
-                    render_pass.clone(),
-                    FramebufferCreateInfo {
-                        attachments: vec![view],
-                        ..FramebufferCreateInfo::default()
-                    },
-                )
-            }).collect::<Result<_, _>>()?;
+        let graph_context = GraphContext {
+            memory_allocator: active_resources.memory_allocator.clone(),
+            descriptor_set_allocator: active_resources.descriptor_set_allocator.clone(),
+            shader: active_resources.shader.clone(),
+            pipelines: active_resources.pipelines.clone(),
+        };
 
-        Ok(SwapchainResources {
-            render_pass,
-            swapchain,
-            images,
-            frame_buffers,
-            draw_resources: None
-        })
+        Self::from_swapchain(graph_context, render_pass_config, settings.clone(), samples, swapchain, images)
     }
     
     pub fn recreate_with_new_size(&self, new_size: [u32; 2]) -> Result<Self, ResourceError> {
@@ -170,108 +180,188 @@ impl SwapchainResources {
             ..self.swapchain.create_info()
         };
         
-        self.recreate(swapchain_recreate_info)
+        self.recreate(swapchain_recreate_info, self.settings.clone(), self.samples)
     }
     
     pub fn recreate_identical(&self) -> Result<Self, ResourceError> {
         let create_info = self.swapchain.create_info();
-        self.recreate(create_info)
+        self.recreate(create_info, self.settings.clone(), self.samples)
     }
 
-    fn recreate(&self, swapchain_recreate_info: SwapchainCreateInfo) -> Result<Self, ResourceError> {
+    pub fn recreate_with_settings(
+        &self,
+        settings: Settings,
+        samples: SampleCount,
+        surface_format: (Format, ColorSpace),
+        present_mode: PresentMode,
+    ) -> Result<Self, ResourceError> {
+        let create_info = SwapchainCreateInfo {
+            image_format: surface_format.0,
+            image_color_space: surface_format.1,
+            present_mode,
+            ..self.swapchain.create_info()
+        };
+        self.recreate(create_info, settings, samples)
+    }
+
+    /// Rebuilds only the frame graph for `settings`, for changes the swapchain doesn't depend on
+    pub fn rebuild_frame_graph(&mut self, settings: Settings) -> Result<(), ResourceError> {
+        self.frame_graph = FrameGraph::new(&self.graph_context, &self.render_pass_config, &settings, self.samples, &self.swapchain)?;
+        self.settings = settings;
+
+        Ok(())
+    }
+
+    fn recreate(&self, swapchain_recreate_info: SwapchainCreateInfo, settings: Settings, samples: SampleCount) -> Result<Self, ResourceError> {
         let (swapchain, images) = self.swapchain.recreate(swapchain_recreate_info)?;
 
-        let render_pass = vulkano::single_pass_renderpass!(
-            self.swapchain.device().clone(),
-            attachments: {
-                color: {
-                    format: self.swapchain.image_format(),
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: Store,
-                },
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {},
-            },
-        )?;
+        Self::from_swapchain(self.graph_context.clone(), self.render_pass_config.clone(), settings, samples, swapchain, images)
+    }
 
-        let frame_buffers = images
-            .iter()
-            .cloned()
-            .map(|image| {
-                let create_info = ImageViewCreateInfo {
-                    view_type: ImageViewType::Dim2d,
-                    subresource_range: ImageSubresourceRange {
-                        aspects: ImageAspects::COLOR,
-                        array_layers: 0..1,
-                        mip_levels: 0..1,
-                    },
-                    ..ImageViewCreateInfo::from_image(&image)
-                };
-
-                let view = ImageView::new(image, create_info)?;
-
-                Framebuffer::new(
-                    self.render_pass.clone(),
-                    FramebufferCreateInfo {
-                        attachments: vec![view],
-                        ..FramebufferCreateInfo::default()
-                    },
-                )
-            }).collect::<Result<_, _>>()?;
-
-        Ok(Self {
-            render_pass,
+    fn from_swapchain(
+        graph_context: GraphContext,
+        render_pass_config: RenderPassConfig,
+        settings: Settings,
+        samples: SampleCount,
+        swapchain: Arc<Swapchain>,
+        images: Vec<Arc<Image>>,
+    ) -> Result<Self, ResourceError> {
+        let frame_graph = FrameGraph::new(&graph_context, &render_pass_config, &settings, samples, &swapchain)?;
+
+        Ok(SwapchainResources {
+            graph_context,
+            render_pass_config,
+            settings,
+            samples,
             swapchain,
             images,
-            frame_buffers,
-            draw_resources: None,
+            frame_graph,
+            previous_frame_end: None,
+            recreate_pending: false,
         })
     }
 }
 
-/// Resources that should be destroyed and recreated alongside the window
-struct DeviceResources {
+/// A window's surface and the swapchain presenting to it
+struct WindowResources {
     window: Arc<Window>,
     vulkan_surface: Arc<Surface>,
+    // What the device can do on this window's surface
     capabilities: Capabilities,
+
+    // Ensures our transient resources cannot live longer than our static ones
+    swapchain_resources: Option<SwapchainResources>,
+}
+
+impl WindowResources {
+    fn new(
+        render_resources: &RenderResources,
+        device: &Arc<Device>,
+        present_queue: &Arc<Queue>,
+        window: Arc<Window>,
+    ) -> Result<Self, ResourceError> {
+        let vulkan_surface = Surface::from_window(render_resources.vulkan_instance.clone(), window.clone())?;
+        Self::from_surface(render_resources, device, present_queue, window, vulkan_surface)
+    }
+
+    fn from_surface(
+        render_resources: &RenderResources,
+        device: &Arc<Device>,
+        present_queue: &Arc<Queue>,
+        window: Arc<Window>,
+        vulkan_surface: Arc<Surface>,
+    ) -> Result<Self, ResourceError> {
+        let physical_device = device.physical_device();
+
+        if !physical_device.surface_support(present_queue.queue_family_index(), &vulkan_surface)? {
+            return Err(ResourceError::UnsupportedWindow);
+        }
+
+        let capabilities = match Capabilities::for_device_on_surface(physical_device, &vulkan_surface, &render_resources.device_requirements) {
+            Ok(capabilities) => capabilities,
+            Err(CapabilityError::Unsuitable(reason)) => {
+                warn!("Cannot present to window, {reason}");
+                return Err(ResourceError::UnsupportedWindow);
+            }
+            Err(CapabilityError::VulkanError(vk_error)) => return Err(vk_error.into()),
+        };
+
+        Ok(WindowResources {
+            window,
+            vulkan_surface,
+            capabilities,
+            swapchain_resources: None,
+        })
+    }
+}
+
+/// Resources that should be destroyed and recreated alongside the device
+struct DeviceResources {
+    // What the device can do on the surface of the window it was chosen for
+    capabilities: Capabilities,
+    // Every device found when this one was chosen
+    devices: Vec<DeviceInfo>,
     device: Arc<Device>,
     graphics_queue: Arc<Queue>,
     present_queue: Arc<Queue>, // Graphics Q and Present Q may be the same,
+    transfer_queue: Arc<Queue>, // The graphics Q when there is no dedicated transfer family
+    compute_queue: Arc<Queue>, // The graphics Q when there is no dedicated compute family
+    memory_allocator: Arc<TrackingMemoryAllocator>,
     command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-
-    // Ensures our transient resources cannot live longer than our static ones
-    swapchain_resources: Option<SwapchainResources>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    shader: IcarusShader,
+    // Graphics pipelines for each render pass, outliving the frame graphs that use them
+    pipelines: PipelineCaches,
+    compute_pipelines: ComputePipelineCache,
+    // Dispatched compute work the next frame waits on
+    pending_compute: Option<ComputeFuture>,
+
+    // The window the device was chosen for comes first
+    windows: Vec<(WindowId, WindowResources)>,
 }
 
 impl DeviceResources {
     pub fn new(render_resources: &RenderResources, window: Arc<Window>) -> Result<Self, ResourceError> {
         let vulkan_surface = Surface::from_window(render_resources.vulkan_instance.clone(), window.clone())?;
 
-        let (physical_device, capabilities) = render_resources.vulkan_instance.enumerate_physical_devices()?
+        let mut candidates = render_resources.vulkan_instance.enumerate_physical_devices()?
             .map(|physical_device| {
-                let caps = Capabilities::for_device_on_surface(&physical_device, &vulkan_surface);
+                let caps = Capabilities::for_device_on_surface(&physical_device, &vulkan_surface, &render_resources.device_requirements);
 
                 (physical_device, caps)
             })
-            .filter_map(|(pd, cap_result)| match cap_result {
+            .map(|(pd, cap_result)| match cap_result {
                 Ok(cap) => {
-                    Some(Ok((pd, cap)))
+                    Ok((pd, Ok(cap)))
                 }
-                Err(CapabilityError::Unsuitable) => {
-                    None
+                Err(CapabilityError::Unsuitable(reason)) => {
+                    Ok((pd, Err(reason)))
                 }
                 Err(CapabilityError::VulkanError(vk_error)) => {
-                    Some(Err(vk_error))
+                    Err(vk_error)
                 }
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .max_by_key(|(_physical_device, d)| d.score())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let devices: Vec<_> = candidates
+            .iter()
+            .map(|(physical_device, caps)| DeviceInfo::new(physical_device, caps.as_ref().map(Capabilities::score).map_err(Clone::clone)))
+            .collect();
+
+        for device in devices.iter() {
+            match &device.suitability {
+                Ok(score) => debug!("Found {device} with a score of {score}"),
+                Err(reason) => debug!("Rejected {device}, {reason}"),
+            }
+        }
+
+        let selected = select_device(&devices, render_resources.preferred_device.as_ref())
             .ok_or(ResourceError::VulkanNoSuitableDevice)?;
 
+        let (physical_device, Ok(capabilities)) = candidates.swap_remove(selected) else {
+            return Err(ResourceError::VulkanNoSuitableDevice);
+        };
+
         let graphics_queue_family_index = physical_device
             .queue_family_properties()
             .iter()
@@ -294,29 +384,70 @@ impl DeviceResources {
             })
             .ok_or(ResourceError::VulkanNoSuitableDevice)??;
 
-        let mut queue_create_info = vec![QueueCreateInfo {
-            queue_family_index: graphics_queue_family_index,
-            ..QueueCreateInfo::default()
-        }];
+        // A family that can only transfer is usually backed by dedicated copy hardware
+        let transfer_queue_family_index = physical_device
+            .queue_family_properties()
+            .iter()
+            .position(|queue_family_properties| {
+                queue_family_properties.queue_flags.contains(QueueFlags::TRANSFER)
+                    && !queue_family_properties.queue_flags.intersects(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+            })
+            .map(|idx| idx as u32);
+
+        // Likewise a compute family without graphics can run alongside the frame
+        let compute_queue_family_index = physical_device
+            .queue_family_properties()
+            .iter()
+            .position(|queue_family_properties| {
+                queue_family_properties.queue_flags.contains(QueueFlags::COMPUTE)
+                    && !queue_family_properties.queue_flags.intersects(QueueFlags::GRAPHICS)
+            })
+            .map(|idx| idx as u32);
 
-        if graphics_queue_family_index != present_queue_family_index {
-            queue_create_info.push(
-                QueueCreateInfo {
-                    queue_family_index: present_queue_family_index,
-                    ..QueueCreateInfo::default()
-                }
-            )
-        }
+        let mut queue_family_indices = vec![graphics_queue_family_index, present_queue_family_index];
+        queue_family_indices.extend(transfer_queue_family_index);
+        queue_family_indices.extend(compute_queue_family_index);
+        queue_family_indices.sort();
+        queue_family_indices.dedup();
 
-        let (device, mut queues) = Device::new(physical_device, DeviceCreateInfo {
-            enabled_features: *capabilities.required_features(),
-            enabled_extensions: *capabilities.required_extensions(),
+        let queue_create_info = queue_family_indices
+            .into_iter()
+            .map(|queue_family_index| QueueCreateInfo {
+                queue_family_index,
+                ..QueueCreateInfo::default()
+            })
+            .collect();
+
+        let (device, queues) = Device::new(physical_device, DeviceCreateInfo {
+            enabled_features: *capabilities.enabled_features(),
+            enabled_extensions: *capabilities.enabled_extensions(),
             queue_create_infos: queue_create_info,
             ..DeviceCreateInfo::default()
         })?;
 
-        let graphics_queue = queues.next().unwrap();
-        let present_queue = queues.next().unwrap_or_else(|| graphics_queue.clone());
+        let queues: Vec<_> = queues.collect();
+        let queue_for_family = |queue_family_index: u32| {
+            queues.iter().find(|queue| queue.queue_family_index() == queue_family_index).cloned()
+        };
+
+        let graphics_queue = queue_for_family(graphics_queue_family_index).unwrap();
+        let present_queue = queue_for_family(present_queue_family_index).unwrap();
+        let transfer_queue = transfer_queue_family_index
+            .and_then(queue_for_family)
+            .unwrap_or_else(|| graphics_queue.clone());
+
+        let compute_queue = compute_queue_family_index
+            .and_then(queue_for_family)
+            .unwrap_or_else(|| graphics_queue.clone());
+
+        if transfer_queue_family_index.is_some() {
+            debug!("Using dedicated transfer queue family {}", transfer_queue.queue_family_index());
+        }
+        if compute_queue_family_index.is_some() {
+            debug!("Using dedicated compute queue family {}", compute_queue.queue_family_index());
+        }
+
+        let memory_allocator = Arc::new(TrackingMemoryAllocator::new(device.clone()));
 
         let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
             device.clone(),
@@ -325,30 +456,138 @@ impl DeviceResources {
             }
         ));
 
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+
+        let shader = IcarusShader::load(device.clone())?;
+
+        let window_resources = WindowResources::from_surface(render_resources, &device, &present_queue, window.clone(), vulkan_surface)?;
+
         Ok(DeviceResources {
-            window,
-            vulkan_surface,
             capabilities,
+            devices,
             device,
             graphics_queue,
             present_queue,
+            transfer_queue,
+            compute_queue,
+            memory_allocator,
             command_buffer_allocator,
+            descriptor_set_allocator,
+            shader,
+            pipelines: PipelineCaches::default(),
+            compute_pipelines: ComputePipelineCache::default(),
+            pending_compute: None,
 
-            swapchain_resources: None,
+            windows: vec![(window.id(), window_resources)],
         })
     }
+
+    fn window(&self, window_id: WindowId) -> Option<&WindowResources> {
+        self.windows
+            .iter()
+            .find(|(id, _)| *id == window_id)
+            .map(|(_, window_resources)| window_resources)
+    }
+
+    /// Leaks every pending frame and compute future instead of dropping them, since dropping a
+    /// fence future waits on it and a lost device may never signal
+    fn forget_futures(&mut self) {
+        if let Some(pending_compute) = self.pending_compute.take() {
+            std::mem::forget(pending_compute);
+        }
+
+        for (_, window_resources) in self.windows.iter_mut() {
+            if let Some(swapchain_resources) = window_resources.swapchain_resources.as_mut() {
+                if let Some(previous_frame_end) = swapchain_resources.previous_frame_end.take() {
+                    std::mem::forget(previous_frame_end);
+                }
+            }
+        }
+    }
+
+    fn window_mut(&mut self, window_id: WindowId) -> Option<&mut WindowResources> {
+        self.windows
+            .iter_mut()
+            .find(|(id, _)| *id == window_id)
+            .map(|(_, window_resources)| window_resources)
+    }
+}
+
+impl WindowResources {
+    /// The surface format to present `color_space` with, warning when it has to fall back
+    fn surface_format(&self, color_space: DisplayColorSpace) -> (Format, ColorSpace) {
+        let surface_format = self.capabilities.surface_format(color_space);
+
+        let is_fallback = match color_space {
+            DisplayColorSpace::Srgb => false,
+            DisplayColorSpace::Hdr10 => surface_format.1 != ColorSpace::Hdr10St2084,
+            DisplayColorSpace::ExtendedSrgb => surface_format.1 != ColorSpace::ExtendedSrgbLinear,
+        };
+        if is_fallback {
+            warn!("{color_space:?} is not supported by this display, falling back to {surface_format:?}");
+        }
+
+        surface_format
+    }
+
+    /// The present mode to use for `vsync`, warning when it has to fall back
+    fn present_mode(&self, vsync: Vsync) -> PresentMode {
+        let present_mode = self.capabilities.present_mode(vsync);
+
+        if vsync.present_modes().first() != Some(&present_mode) {
+            warn!("Vsync {vsync:?} is not supported by this display, falling back to {present_mode:?}");
+        }
+
+        present_mode
+    }
+}
+
+/// The index of the device to use, the preferred one if it's suitable otherwise the highest scoring
+fn select_device(devices: &[DeviceInfo], preferred: Option<&Override<(u32, u32)>>) -> Option<usize> {
+    if let Some(Override { value: id, source }) = preferred {
+        match devices.iter().position(|device| device.matches(*id)) {
+            Some(idx) => match &devices[idx].suitability {
+                Ok(_) => {
+                    info!("Using {}, chosen because it was preferred by {source}", devices[idx]);
+                    return Some(idx);
+                }
+                Err(reason) => warn!("Preferred device {} from {source} is unsuitable, {reason}", devices[idx]),
+            },
+            None => warn!("Preferred device {:04x}:{:04x} from {source} was not found", id.0, id.1),
+        }
+    }
+
+    let (idx, score) = devices
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, device)| device.suitability.as_ref().ok().map(|&score| (idx, score)))
+        .max_by_key(|&(_idx, score)| score)?;
+
+    info!("Using {}, chosen because it has the highest score of {score}", devices[idx]);
+
+    Some(idx)
 }
 
 /// Resources that live as long as the application
 pub struct RenderResources {
     vulkan_instance: Arc<Instance>,
+    render_pass_config: RenderPassConfig,
+    settings: Settings,
+    // The device to use when suitable and where it was asked for
+    preferred_device: Option<Override<(u32, u32)>>,
+    device_requirements: DeviceRequirements,
+    // Outlives any one device so assets can be restored onto the next
+    asset_tracker: Arc<AssetTracker>,
 
     // Ensures our active resources cannot live longer than our static ones
     device_resources: Option<DeviceResources>,
 }
 
 impl RenderResources {
-    pub fn create(event_loop: &EventLoop<()>, application_name: Option<String>, application_version: Version) -> Result<Self, ResourceError> {
+    pub fn create(event_loop: &EventLoop<()>, application_name: Option<String>, application_version: Version, render_pass_config: RenderPassConfig, settings: Settings, preferred_device: Option<Override<(u32, u32)>>, device_requirements: DeviceRequirements) -> Result<Self, ResourceError> {
         let vk_lib = VulkanLibrary::new()?;
 
         is_required_layer_support_available(&vk_lib)
@@ -368,8 +607,12 @@ impl RenderResources {
             debug_utils_messengers.push(create_info);
         };
 
+        let optional_extensions = vk_lib.supported_extensions().intersection(&OPTIONAL_INSTANCE_EXTENSIONS);
+
         let vulkan_instance = Instance::new(vk_lib, InstanceCreateInfo {
-            enabled_extensions: Surface::required_extensions(&event_loop)?.union(&REQUIRED_INSTANCE_EXTENSIONS),
+            enabled_extensions: Surface::required_extensions(&event_loop)?
+                .union(&REQUIRED_INSTANCE_EXTENSIONS)
+                .union(&optional_extensions),
             enabled_layers: get_required_layers(),
             debug_utils_messengers,
             application_name,
@@ -379,13 +622,20 @@ impl RenderResources {
 
         Ok(RenderResources {
             vulkan_instance,
+            render_pass_config,
+            settings,
+            preferred_device,
+            device_requirements,
+            asset_tracker: Arc::new(AssetTracker::default()),
             device_resources: None,
         })
     }
 
-    pub fn destroy_swapchain(&mut self) -> Result<&mut Self, ResourceError> {
+    pub fn destroy_swapchain(&mut self, window_id: WindowId) -> Result<&mut Self, ResourceError> {
         if let Some(active_resources) = &mut self.device_resources {
-            active_resources.swapchain_resources = None;
+            active_resources.window_mut(window_id)
+                .ok_or(ResourceError::UnknownWindow(window_id))?
+                .swapchain_resources = None;
         } else {
             warn!("Destroying swapchain without active resources!");
         }
@@ -393,16 +643,36 @@ impl RenderResources {
         Ok(self)
     }
 
-    pub fn recreate_swapchain(&mut self) -> Result<&mut Self, ResourceError> {
+    pub fn recreate_swapchain(&mut self, window_id: WindowId) -> Result<&mut Self, ResourceError> {
         if let Some(device_resources) = &mut self.device_resources {
-            let swapchain_resources = match device_resources.swapchain_resources.take() {
-                Some(mut swapchain_resources) => {
-                    swapchain_resources.recreate_with_new_size(device_resources.window.inner_size().into())?
+            let window_resources = device_resources.window(window_id)
+                .ok_or(ResourceError::UnknownWindow(window_id))?;
+
+            // A minimized window can't have a swapchain, keep the old one until it has a size again
+            if window_resources.window.inner_size().width == 0 || window_resources.window.inner_size().height == 0 {
+                return Err(ResourceError::ZeroSizedWindow(window_id));
+            }
+
+            let result = match &window_resources.swapchain_resources {
+                Some(swapchain_resources) => {
+                    swapchain_resources.recreate_with_new_size(window_resources.window.inner_size().into())
                 },
-                None => SwapchainResources::new(device_resources)?
+                None => SwapchainResources::new(device_resources, window_resources, self.render_pass_config.clone(), &self.settings)
             };
 
-            device_resources.swapchain_resources = Some(swapchain_resources);
+            let window_resources = device_resources.window_mut(window_id)
+                .ok_or(ResourceError::UnknownWindow(window_id))?;
+
+            // The old swapchain is only replaced on success, on failure it is kept and recreated on the next draw
+            match result {
+                Ok(swapchain_resources) => window_resources.swapchain_resources = Some(swapchain_resources),
+                Err(error) => {
+                    if let Some(swapchain_resources) = &mut window_resources.swapchain_resources {
+                        swapchain_resources.recreate_pending = true;
+                    }
+                    return Err(error);
+                }
+            }
         } else {
             warn!("Attempt to recreate swapchain without device resources!");
         }
@@ -410,8 +680,184 @@ impl RenderResources {
         Ok(self)
     }
 
+    /// Starts presenting to another window on the current device and returns its id.
+    /// Call `recreate_swapchain` with the id before drawing to it
+    pub fn add_window(&mut self, window: Arc<Window>) -> Result<WindowId, ResourceError> {
+        let device_resources = self.device_resources
+            .as_ref()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        let window_id = window.id();
+        let window_resources = WindowResources::new(self, &device_resources.device, &device_resources.present_queue, window)?;
+
+        if let Some(device_resources) = &mut self.device_resources {
+            device_resources.windows.push((window_id, window_resources));
+        }
+
+        Ok(window_id)
+    }
+
+    /// Stops presenting to a window, destroying its swapchain and surface.
+    /// The window the device was created for can't be removed without destroying the device resources
+    pub fn remove_window(&mut self, window_id: WindowId) -> Result<&mut Self, ResourceError> {
+        let device_resources = self.device_resources
+            .as_mut()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        let idx = device_resources.windows
+            .iter()
+            .position(|(id, _)| *id == window_id)
+            .ok_or(ResourceError::UnknownWindow(window_id))?;
+
+        if idx == 0 {
+            warn!("Refusing to remove the primary window {window_id:?}, destroy the device resources instead");
+        } else {
+            device_resources.windows.remove(idx);
+        }
+
+        Ok(self)
+    }
+
+    /// The ids of every window being presented to, the primary window first
+    pub fn windows(&self) -> Vec<WindowId> {
+        self.device_resources
+            .iter()
+            .flat_map(|device_resources| device_resources.windows.iter().map(|(id, _)| *id))
+            .collect()
+    }
+
+    /// The settings as changed at runtime
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Changes the anti-aliasing level, rebuilding the render targets if they exist
+    pub fn set_msaa(&mut self, msaa: Msaa) -> Result<&mut Self, ResourceError> {
+        self.settings.msaa = msaa;
+        self.apply_settings()
+    }
+
+    /// Changes the resolution the scene is rendered at, `None` renders at `render_size`
+    pub fn set_render_scale(&mut self, render_scale: Option<f32>, upscale_filter: UpscaleFilter) -> Result<&mut Self, ResourceError> {
+        self.settings.render_scale = render_scale;
+        self.settings.upscale_filter = upscale_filter;
+        self.rebuild_frame_graphs()
+    }
+
+    /// Turns every post processing pass using `effect` on or off
+    pub fn set_post_process_enabled(&mut self, effect: PostEffect, enabled: bool) -> Result<&mut Self, ResourceError> {
+        self.settings.post_processing
+            .iter_mut()
+            .filter(|pass| pass.effect == effect)
+            .for_each(|pass| pass.enabled = enabled);
+        self.rebuild_frame_graphs()
+    }
+
+    /// Changes the color space frames are presented in, falling back to sRGB if the display can't do it
+    pub fn set_color_space(&mut self, color_space: DisplayColorSpace) -> Result<&mut Self, ResourceError> {
+        self.settings.color_space = color_space;
+        self.apply_settings()
+    }
+
+    /// The format and color space frames are actually presented to a window in, `None` without a swapchain
+    pub fn surface_format(&self, window_id: WindowId) -> Option<(Format, ColorSpace)> {
+        let swapchain = &self.device_resources.as_ref()?.window(window_id)?.swapchain_resources.as_ref()?.swapchain;
+
+        Some((swapchain.image_format(), swapchain.image_color_space()))
+    }
+
+    /// Changes how presenting waits for the display
+    pub fn set_vsync(&mut self, vsync: Vsync) -> Result<&mut Self, ResourceError> {
+        self.settings.vsync = vsync;
+        self.apply_settings()
+    }
+
+    /// Limits the frame rate when presenting doesn't wait for the display, `None` is uncapped
+    pub fn set_frame_rate_cap(&mut self, frame_rate_cap: Option<u32>) -> &mut Self {
+        self.settings.frame_rate_cap = frame_rate_cap;
+        self
+    }
+
+    /// The minimum time between frames under the frame rate cap, `None` when uncapped or when every
+    /// window waits on the display, which already limits the frame rate
+    pub fn frame_interval(&self) -> Option<Duration> {
+        let is_unsynced = self.device_resources
+            .as_ref()?
+            .windows
+            .iter()
+            .filter_map(|(_, window_resources)| window_resources.swapchain_resources.as_ref())
+            .any(|swapchain_resources| matches!(swapchain_resources.swapchain.present_mode(), PresentMode::Immediate | PresentMode::Mailbox));
+
+        self.settings.frame_rate_cap
+            .filter(|&cap| is_unsynced && cap > 0)
+            .map(|cap| Duration::from_secs_f64(1.0 / cap as f64))
+    }
+
+    /// Limits the frame rate while no window has focus, `None` keeps drawing as usual
+    pub fn set_unfocused_frame_rate(&mut self, unfocused_frame_rate: Option<u32>) -> &mut Self {
+        self.settings.unfocused_frame_rate = unfocused_frame_rate;
+        self
+    }
+
+    /// The present mode frames are actually presented to a window with, `None` without a swapchain
+    pub fn present_mode(&self, window_id: WindowId) -> Option<PresentMode> {
+        let swapchain = &self.device_resources.as_ref()?.window(window_id)?.swapchain_resources.as_ref()?.swapchain;
+
+        Some(swapchain.present_mode())
+    }
+
+    /// Every device found on the primary window's surface with its score or why it was rejected,
+    /// `None` without device resources
+    pub fn available_devices(&self) -> Option<&[DeviceInfo]> {
+        Some(&self.device_resources.as_ref()?.devices)
+    }
+
+    /// The features enabled on the current device, required plus whichever optional ones it supports.
+    /// `None` without device resources
+    pub fn enabled_features(&self) -> Option<&DeviceFeatures> {
+        Some(self.device_resources.as_ref()?.capabilities.enabled_features())
+    }
+
+    /// The extensions enabled on the current device, required plus whichever optional ones it supports.
+    /// `None` without device resources
+    pub fn enabled_extensions(&self) -> Option<&DeviceExtensions> {
+        Some(self.device_resources.as_ref()?.capabilities.enabled_extensions())
+    }
+
+    /// Rebuilds every window's frame graph for the current settings, keeping the swapchains
+    fn rebuild_frame_graphs(&mut self) -> Result<&mut Self, ResourceError> {
+        if let Some(device_resources) = &mut self.device_resources {
+            for (_, window_resources) in device_resources.windows.iter_mut() {
+                if let Some(swapchain_resources) = &mut window_resources.swapchain_resources {
+                    swapchain_resources.rebuild_frame_graph(self.settings.clone())?;
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Rebuilds the swapchains and render targets of every window to match the current settings
+    fn apply_settings(&mut self) -> Result<&mut Self, ResourceError> {
+        if let Some(device_resources) = &mut self.device_resources {
+            let samples = device_resources.capabilities.clamp_samples(self.settings.msaa);
+
+            for (_, window_resources) in device_resources.windows.iter_mut() {
+                let surface_format = window_resources.surface_format(self.settings.color_space);
+                let present_mode = window_resources.present_mode(self.settings.vsync);
+
+                if let Some(swapchain_resources) = &mut window_resources.swapchain_resources {
+                    *swapchain_resources = swapchain_resources.recreate_with_settings(self.settings.clone(), samples, surface_format, present_mode)?;
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
     pub fn create_device_resources(&mut self,  window: Arc<Window>) -> Result<&mut Self, ResourceError> {
         self.device_resources = Some(DeviceResources::new(self, window)?);
+        self.asset_tracker.set_context(&self.asset_context()?);
 
         Ok(self)
     }
@@ -422,51 +868,335 @@ impl RenderResources {
         self
     }
 
-    pub fn draw(&self, pipeline: Arc<GraphicsPipeline>, vertex_buffer: Vec<Subbuffer<[[f32;3]]>>) -> Result<(), ResourceError> {
+    /// Replaces a lost device with a new one on the same windows, call `create_swapchains` and `restore_assets`
+    /// afterwards. Returns the windows that couldn't be moved to the new device, which are dropped and should be closed
+    pub fn recreate_device_resources(&mut self) -> Result<Vec<WindowId>, ResourceError> {
+        let mut lost_resources = self.device_resources
+            .take()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        // Nothing submitted to the lost device can be waited on
+        lost_resources.forget_futures();
+        self.asset_tracker.forget_uploads();
+
+        let mut windows = lost_resources
+            .windows
+            .into_iter()
+            .map(|(_, window_resources)| window_resources.window);
+
+        // The device is chosen for the primary window, the rest are added to it
+        let primary_window = windows.next().ok_or(ResourceError::MissingRequiredResources)?;
+        self.create_device_resources(primary_window)?;
+
+        let mut unsupported = Vec::new();
+        for window in windows {
+            let window_id = window.id();
+            match self.add_window(window) {
+                Ok(_) => {}
+                Err(ResourceError::UnsupportedWindow) => {
+                    warn!("The new device cannot present to window {window_id:?}, dropping it");
+                    unsupported.push(window_id);
+                }
+                // The window is already gone from the device, so it has to be closed all the same
+                Err(e) => {
+                    error!("Failed to add window {window_id:?} to the new device, dropping it! {e}");
+                    unsupported.push(window_id);
+                }
+            }
+        }
+
+        Ok(unsupported)
+    }
+
+    /// Creates a swapchain for every window, such as after `recreate_device_resources`. Minimized
+    /// windows are skipped and get theirs when they're resized
+    pub fn create_swapchains(&mut self) -> Result<&mut Self, ResourceError> {
+        for window_id in self.windows() {
+            match self.recreate_swapchain(window_id) {
+                Ok(_) => {}
+                Err(ResourceError::ZeroSizedWindow(_)) => debug!("Window {window_id:?} is minimized, creating its swapchain once it's restored"),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Uploads every live `ModelData` and `Texture` again from the CPU data they kept,
+    /// for after the device resources were recreated. Returns how many failed to restore
+    pub fn restore_assets(&self) -> Result<usize, ResourceError> {
+        Ok(self.asset_tracker.reupload_all(&self.asset_context()?))
+    }
+
+    /// The allocator for buffers and images on the current device, `None` without device resources
+    pub fn memory_allocator(&self) -> Option<&Arc<TrackingMemoryAllocator>> {
+        Some(&self.device_resources.as_ref()?.memory_allocator)
+    }
+
+    /// The allocator for descriptor sets on the current device, `None` without device resources
+    pub fn descriptor_set_allocator(&self) -> Option<&Arc<StandardDescriptorSetAllocator>> {
+        Some(&self.device_resources.as_ref()?.descriptor_set_allocator)
+    }
+
+    /// The allocator for command buffers on the current device, `None` without device resources
+    pub fn command_buffer_allocator(&self) -> Option<&Arc<StandardCommandBufferAllocator>> {
+        Some(&self.device_resources.as_ref()?.command_buffer_allocator)
+    }
+
+    /// Memory usage of each heap on the current device, `None` without device resources
+    pub fn memory_stats(&self) -> Option<Vec<HeapStats>> {
+        Some(HeapStats::collect(&self.device_resources.as_ref()?.memory_allocator))
+    }
+
+    /// The compute pipeline for a compute entry point in `icarus-shaders`
+    pub fn compute_pipeline(&mut self, entry_point: &'static str) -> Result<Arc<ComputePipeline>, ResourceError> {
+        let device_resources = self.device_resources
+            .as_mut()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        Ok(device_resources.compute_pipelines.get_or_create(&device_resources.shader, entry_point)?)
+    }
+
+    /// A storage buffer holding `data`, readable from the CPU and usable by both compute and graphics work
+    pub fn storage_buffer<T, I>(&self, data: I) -> Result<Subbuffer<[T]>, ResourceError>
+    where
+        T: BufferContents,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
         let device_resources = self.device_resources
             .as_ref()
             .ok_or(ResourceError::MissingRequiredResources)?;
 
-        let swapchain_resources = device_resources.swapchain_resources
+        let graphics_family = device_resources.graphics_queue.queue_family_index();
+        let compute_family = device_resources.compute_queue.queue_family_index();
+        let sharing = if graphics_family == compute_family {
+            Sharing::Exclusive
+        } else {
+            Sharing::Concurrent([graphics_family, compute_family].into_iter().collect())
+        };
+
+        let buffer = Buffer::from_iter(
+            device_resources.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::VERTEX_BUFFER,
+                sharing,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            data,
+        )?;
+
+        Ok(buffer)
+    }
+
+    /// Submits `pipeline` to the compute queue with storage buffers bound through `descriptor_writes`.
+    /// The next frame waits for it, use `wait_for_compute` to read the results on the CPU
+    pub fn dispatch<W>(&mut self, pipeline: &Arc<ComputePipeline>, descriptor_writes: W, group_counts: [u32; 3]) -> Result<(), ResourceError>
+    where
+        W: IntoIterator<Item = WriteDescriptorSet>,
+    {
+        self.record_dispatch(pipeline, descriptor_writes, group_counts, |_builder| Ok(()))
+    }
+
+    /// Like `dispatch` with push constants for the shader
+    pub fn dispatch_with_constants<W, Pc>(
+        &mut self,
+        pipeline: &Arc<ComputePipeline>,
+        descriptor_writes: W,
+        push_constants: Pc,
+        group_counts: [u32; 3],
+    ) -> Result<(), ResourceError>
+    where
+        W: IntoIterator<Item = WriteDescriptorSet>,
+        Pc: BufferContents,
+    {
+        let layout = pipeline.layout().clone();
+        self.record_dispatch(pipeline, descriptor_writes, group_counts, move |builder| {
+            builder.push_constants(layout, 0, push_constants)?;
+            Ok(())
+        })
+    }
+
+    fn record_dispatch<W, F>(&mut self, pipeline: &Arc<ComputePipeline>, descriptor_writes: W, group_counts: [u32; 3], record_constants: F) -> Result<(), ResourceError>
+    where
+        W: IntoIterator<Item = WriteDescriptorSet>,
+        F: FnOnce(&mut CommandBuilder) -> Result<(), Box<ValidationError>>,
+    {
+        let device_resources = self.device_resources
+            .as_mut()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            device_resources.command_buffer_allocator.clone(),
+            device_resources.compute_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        builder.bind_pipeline_compute(pipeline.clone())?;
+
+        if let Some(layout) = pipeline.layout().set_layouts().first() {
+            let descriptor_set = DescriptorSet::new(
+                device_resources.descriptor_set_allocator.clone(),
+                layout.clone(),
+                descriptor_writes,
+                [],
+            )?;
+
+            builder.bind_descriptor_sets(PipelineBindPoint::Compute, pipeline.layout().clone(), 0, descriptor_set)?;
+        }
+
+        record_constants(&mut builder)?;
+        unsafe { builder.dispatch(group_counts) }?;
+
+        let command_buffer = builder.build()?;
+
+        // Dispatches run in order, each after the last
+        let previous = match device_resources.pending_compute.take() {
+            Some(pending_compute) => pending_compute.boxed(),
+            None => sync::now(device_resources.device.clone()).boxed(),
+        };
+
+        // Submitted straight away so the GPU can start on it while the CPU carries on with the frame
+        let future = previous
+            .then_execute(device_resources.compute_queue.clone(), command_buffer)?
+            .boxed()
+            .then_signal_semaphore_and_flush()?;
+
+        device_resources.pending_compute = Some(future);
+
+        Ok(())
+    }
+
+    /// Blocks until every dispatch so far has finished, so their buffers can be read on the CPU
+    pub fn wait_for_compute(&mut self) -> Result<(), ResourceError> {
+        let device_resources = self.device_resources
+            .as_mut()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        // Dispatches a frame already waits on have left no future behind, but are all on the compute queue.
+        // Any still pending keeps its semaphore for the next frame, which finds it signalled
+        device_resources.compute_queue.with(|mut queue| queue.wait_idle())?;
+
+        Ok(())
+    }
+
+    /// Builds the context assets use to upload themselves to the current device
+    pub fn asset_context(&self) -> Result<AssetContext, ResourceError> {
+        let device_resources = self.device_resources
             .as_ref()
             .ok_or(ResourceError::MissingRequiredResources)?;
 
+        Ok(AssetContext {
+            memory_allocator: device_resources.memory_allocator.clone(),
+            command_buffer_allocator: device_resources.command_buffer_allocator.clone(),
+            queue: device_resources.graphics_queue.clone(),
+            transfer_queue: device_resources.transfer_queue.clone(),
+            tracker: self.asset_tracker.clone(),
+        })
+    }
 
+    /// Draws a frame to a window, batching the drawables by pipeline so each is only bound once
+    pub fn draw<'a, I>(&mut self, window_id: WindowId, drawables: I) -> Result<(), ResourceError>
+    where
+        I: IntoIterator<Item = (&'a ModelData, &'a Material)>,
+    {
+        let device_resources = self.device_resources
+            .as_mut()
+            .ok_or(ResourceError::MissingRequiredResources)?;
 
-        let cb = swapchain_resources.frame_buffers
-            .iter()
-            .map(|fb| -> Result<Arc<_>, ResourceError> {
-                let mut builder = AutoCommandBufferBuilder::primary(
-                    device_resources.command_buffer_allocator.clone(),
-                    device_resources.present_queue.queue_family_index(),
-                    CommandBufferUsage::MultipleSubmit,
-                )?;
-
-                builder
-                    .begin_render_pass(
-                        RenderPassBeginInfo {
-                            clear_values: vec![Some([0.1, 0.1, 0.1, 1.0].into())],
-                            ..RenderPassBeginInfo::framebuffer(fb.clone())
-                        },
-                        SubpassBeginInfo {
-                            contents: SubpassContents::Inline,
-                            ..Default::default()
-                        },
-                    )?
-                    .bind_pipeline_graphics(pipeline.clone())?
-                    .bind_vertex_buffers(0, vertex_buffer.clone())?;
-
-                unsafe {
-                    builder
-                        .draw(vertex_buffer.len() as u32, 1, 0, 0)
-                }?;
-
-                builder
-                    .end_render_pass(SubpassEndInfo::default())?;
-
-                Ok(builder.build()?)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+        let window_resources = device_resources.windows
+            .iter_mut()
+            .find(|(id, _)| *id == window_id)
+            .map(|(_, window_resources)| window_resources)
+            .ok_or(ResourceError::UnknownWindow(window_id))?;
+
+        let window = &window_resources.window;
+        let swapchain_resources = window_resources.swapchain_resources
+            .as_mut()
+            .ok_or(ResourceError::MissingRequiredResources)?;
+
+        if let Some(previous_frame_end) = swapchain_resources.previous_frame_end.as_mut() {
+            previous_frame_end.cleanup_finished();
+        }
+
+        if swapchain_resources.recreate_pending {
+            let size = window.inner_size();
+            if size.width == 0 || size.height == 0 {
+                return Err(ResourceError::ZeroSizedWindow(window_id));
+            }
+
+            trace!("Swapchain is suboptimal and must be recreated");
+            *swapchain_resources = swapchain_resources.recreate_with_new_size(window.inner_size().into())?;
+        }
+
+        let (image_index, is_suboptimal, acquire_future) = match swapchain::acquire_next_image(swapchain_resources.swapchain.clone(), Some(ACQUIRE_TIMEOUT))
+            .map_err(Validated::unwrap)
+        {
+            Ok(acquired) => acquired,
+            Err(VulkanError::OutOfDate) => {
+                swapchain_resources.recreate_pending = true;
+                return Ok(());
+            }
+            Err(VulkanError::Timeout | VulkanError::NotReady) => return Err(ResourceError::AcquireTimeout),
+            Err(e) => return Err(e.into()),
+        };
+
+        swapchain_resources.recreate_pending |= is_suboptimal;
+
+        let mut frame = FrameData::default();
+        for (model_data, material) in drawables {
+            frame.batches
+                .entry(material.pipeline_key())
+                .or_default()
+                .push((model_data.clone(), material.clone()));
+        }
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            device_resources.command_buffer_allocator.clone(),
+            device_resources.graphics_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        let frame_graph = &mut swapchain_resources.frame_graph;
+        frame_graph.graph.set_image(frame_graph.swapchain_image, swapchain_resources.images[image_index as usize].clone())?;
+        frame_graph.graph.execute(&mut builder, &mut frame)?;
+
+        let command_buffer = builder.build()?;
+
+        let previous_frame_end = swapchain_resources.previous_frame_end
+            .take()
+            .unwrap_or_else(|| sync::now(device_resources.device.clone()).boxed());
+
+        // The frame may read what was dispatched. Only this frame waits on it, the frames of other
+        // windows are submitted after it on the same queue so they're ordered after the compute too
+        let previous_frame_end = match device_resources.pending_compute.take() {
+            Some(pending_compute) => previous_frame_end.join(pending_compute).boxed(),
+            None => previous_frame_end,
+        };
+
+        let future = previous_frame_end
+            .join(acquire_future)
+            .then_execute(device_resources.graphics_queue.clone(), command_buffer)?
+            .then_swapchain_present(
+                device_resources.present_queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(swapchain_resources.swapchain.clone(), image_index),
+            )
+            .then_signal_fence_and_flush();
+
+        match future.map_err(Validated::unwrap) {
+            Ok(future) => {
+                swapchain_resources.previous_frame_end = Some(future.boxed());
+            }
+            Err(VulkanError::OutOfDate) => {
+                swapchain_resources.recreate_pending = true;
+            }
+            Err(e) => return Err(e.into()),
+        }
 
         Ok(())
     }
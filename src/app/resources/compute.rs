@@ -0,0 +1,50 @@
+use crate::app::resources::PipelineError;
+use crate::app::shaders::IcarusShader;
+use std::collections::HashMap;
+use std::sync::Arc;
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{ComputePipeline, PipelineLayout, PipelineShaderStageCreateInfo};
+
+/// Compute pipelines by entry point, each created the first time it's asked for
+#[derive(Default)]
+pub(crate) struct ComputePipelineCache {
+    pipelines: HashMap<&'static str, Arc<ComputePipeline>>,
+}
+
+impl ComputePipelineCache {
+    pub fn get_or_create(&mut self, shader: &IcarusShader, entry_point: &'static str) -> Result<Arc<ComputePipeline>, PipelineError> {
+        if let Some(pipeline) = self.pipelines.get(entry_point) {
+            return Ok(pipeline.clone());
+        }
+
+        let pipeline = Self::create(shader, entry_point)?;
+        self.pipelines.insert(entry_point, pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    fn create(shader: &IcarusShader, entry_point: &'static str) -> Result<Arc<ComputePipeline>, PipelineError> {
+        let device = shader.device();
+
+        let stage = PipelineShaderStageCreateInfo::new(
+            shader
+                .entry_point(entry_point)
+                .ok_or(PipelineError::MissingEntryPoint(entry_point))?,
+        );
+
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())?,
+        )?;
+
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )?;
+
+        Ok(pipeline)
+    }
+}
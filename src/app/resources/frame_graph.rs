@@ -0,0 +1,269 @@
+use crate::app::resources::post_process::{add_hdr10_encode_pass, add_post_process_chain};
+use crate::app::resources::{MaterialConstants, PipelineCaches, RenderPassConfig, RenderPassKey, ResourceError};
+use crate::app::settings::Settings;
+use crate::app::shaders::IcarusShader;
+use crate::ecs::core::components::{Material, ModelData};
+use crate::render::{ImageAccess, ImageDescription, ImageId, PassRecorder, PipelineKey, RenderGraph, RenderGraphBuilder, RenderGraphError, VertexLayout};
+use log::warn;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use vulkano::command_buffer::{BlitImageInfo, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents, SubpassEndInfo};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::DescriptorSet;
+use vulkano::device::DeviceOwned;
+use vulkano::format::{Format, NumericFormat};
+use vulkano::image::sampler::Filter;
+use vulkano::image::{ImageUsage, SampleCount};
+use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo};
+use vulkano::swapchain::{ColorSpace, Swapchain};
+
+/// The scene is drawn in HDR so post processing has the full range to work with
+const SCENE_COLOR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// What the frame graph's passes need from the device
+#[derive(Clone)]
+pub(crate) struct GraphContext {
+    pub memory_allocator: Arc<dyn MemoryAllocator>,
+    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pub shader: IcarusShader,
+    pub pipelines: PipelineCaches,
+}
+
+/// Handed to the frame graph's passes each frame
+#[derive(Default)]
+pub(crate) struct FrameData {
+    pub batches: BTreeMap<PipelineKey, Vec<(ModelData, Material)>>,
+}
+
+/// The render graph drawing a frame into a swapchain image
+pub(crate) struct FrameGraph {
+    pub graph: RenderGraph<FrameData>,
+    pub swapchain_image: ImageId,
+}
+
+impl FrameGraph {
+    pub fn new(
+        context: &GraphContext,
+        render_pass_config: &RenderPassConfig,
+        settings: &Settings,
+        samples: SampleCount,
+        swapchain: &Swapchain,
+    ) -> Result<Self, ResourceError> {
+        let render_extent = settings.render_extent(swapchain.image_extent());
+
+        let mut builder = RenderGraphBuilder::new();
+
+        let scene_color = builder.create_image("scene_color", ImageDescription {
+            format: SCENE_COLOR_FORMAT,
+            extent: render_extent,
+            samples: SampleCount::Sample1,
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC | ImageUsage::SAMPLED,
+        });
+        let swapchain_image = builder.import_image("swapchain");
+
+        add_scene_pass(&mut builder, context, render_pass_config, samples, scene_color)?;
+        let is_hdr10 = swapchain.image_color_space() == ColorSpace::Hdr10St2084;
+        // Either way the output is encoded when it's written, so gamma correcting would apply it twice
+        let is_encoded = is_hdr10 || swapchain.image_format().numeric_format_color() == Some(NumericFormat::SRGB);
+
+        let post_processed = add_post_process_chain(&mut builder, context, &settings.post_processing, is_encoded, scene_color)?;
+        let output = match is_hdr10 {
+            true => add_hdr10_encode_pass(&mut builder, context, post_processed)?,
+            false => post_processed,
+        };
+        add_upscale_pass(&mut builder, output, swapchain_image, settings.upscale_filter.into());
+
+        Ok(FrameGraph {
+            graph: builder.build(context.memory_allocator.clone())?,
+            swapchain_image,
+        })
+    }
+}
+
+/// Draws the batched scene into `color`
+fn add_scene_pass(
+    builder: &mut RenderGraphBuilder<FrameData>,
+    context: &GraphContext,
+    render_pass_config: &RenderPassConfig,
+    samples: SampleCount,
+    color: ImageId,
+) -> Result<(), RenderGraphError> {
+    let is_multisampled = samples != SampleCount::Sample1;
+
+    // Color and extent always match the resolved image
+    let (format, extent) = {
+        let description = builder.description(color)?;
+        (description.format, description.extent)
+    };
+
+    let multisampled_color = is_multisampled.then(|| builder.create_image("scene_multisampled_color", ImageDescription {
+        format,
+        extent,
+        samples,
+        usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+    }));
+
+    let depth = render_pass_config.depth_format.map(|depth_format| builder.create_image("scene_depth", ImageDescription {
+        format: depth_format,
+        extent,
+        samples,
+        usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+    }));
+
+    let accesses: Vec<_> = [
+        Some((color, ImageAccess::ColorAttachment)),
+        multisampled_color.map(|id| (id, ImageAccess::ColorAttachment)),
+        depth.map(|id| (id, ImageAccess::DepthStencilAttachment)),
+    ].into_iter().flatten().collect();
+    let context = context.clone();
+    let render_pass_config = render_pass_config.clone();
+
+    builder.add_pass("scene", &accesses, move |images, transitions| {
+        let to_pass_error = |error: Box<dyn std::error::Error + Send + Sync>| RenderGraphError::PassError { pass: "scene", error };
+
+        // Whatever reads the scene next decides the layout it's left in
+        let final_layout = transitions.get(color)?.final_layout();
+        let pipeline_cache = context.pipelines
+            .get_or_create(RenderPassKey::Scene { samples, final_layout }, || {
+                render_pass_config.render_pass(format, samples, final_layout, transitions.dependencies(), context.memory_allocator.device().clone())
+            })
+            .map_err(|error| to_pass_error(Box::new(error)))?;
+        let render_pass = pipeline_cache.lock().unwrap().render_pass().clone();
+
+        // Attachment order must match `RenderPassConfig::render_pass`: color, depth, resolve
+        let attachments = match multisampled_color {
+            Some(multisampled_color) => [Some(multisampled_color), depth, Some(color)],
+            None => [Some(color), depth, None],
+        };
+
+        let frame_buffer = Framebuffer::new(
+            render_pass,
+            FramebufferCreateInfo {
+                attachments: attachments
+                    .into_iter()
+                    .flatten()
+                    .map(|id| images.view(id))
+                    .collect::<Result<_, _>>()?,
+                ..FramebufferCreateInfo::default()
+            },
+        )?;
+
+        let clear_values = render_pass_config.clear_values(samples);
+
+        let recorder: PassRecorder<FrameData> = Box::new(move |builder, _images, frame| {
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: clear_values.clone(),
+                        ..RenderPassBeginInfo::framebuffer(frame_buffer.clone())
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::Inline,
+                        ..Default::default()
+                    },
+                )?
+                .set_viewport(0, [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [extent[0] as f32, extent[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }].into_iter().collect())?;
+
+            for (key, batch) in frame.batches.iter() {
+                let pipeline = pipeline_cache
+                    .lock()
+                    .unwrap()
+                    .get_or_create(&context.shader, key)
+                    .map_err(|error| to_pass_error(Box::new(error)))?;
+                builder.bind_pipeline_graphics(pipeline.clone())?;
+
+                for (model_data, material) in batch {
+                    if !model_data.is_ready() {
+                        continue;
+                    }
+
+                    let textures = material.textures
+                        .iter()
+                        .map(|texture| texture.get().filter(|texture| texture.is_ready()))
+                        .collect::<Option<Vec<_>>>();
+
+                    // Skip anything whose textures are still loading or uploading rather than drawing it wrong
+                    let Some(textures) = textures else {
+                        continue;
+                    };
+
+                    match key.vertex_layout {
+                        VertexLayout::None => {}
+                        VertexLayout::Position => {
+                            builder.bind_vertex_buffers(0, model_data.positions())?;
+                        }
+                        VertexLayout::PositionTexCoord => {
+                            let Some(tex_coords) = model_data.tex_coords() else {
+                                warn!("Skipping draw of a model without texture coordinates using {}", key.vertex_shader);
+                                continue;
+                            };
+                            builder.bind_vertex_buffers(0, (model_data.positions(), tex_coords))?;
+                        }
+                    }
+
+                    if let Some(layout) = pipeline.layout().set_layouts().first() {
+                        let descriptor_set = DescriptorSet::new(
+                            context.descriptor_set_allocator.clone(),
+                            layout.clone(),
+                            textures
+                                .iter()
+                                .enumerate()
+                                .flat_map(|(idx, texture)| texture.descriptor_writes(2 * idx as u32)),
+                            [],
+                        )?;
+
+                        builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set)?;
+                    }
+
+                    builder.push_constants(pipeline.layout().clone(), 0, MaterialConstants {
+                        base_color: material.base_color,
+                    })?;
+
+                    match model_data.indices() {
+                        Some(indices) => {
+                            let index_count = indices.len() as u32;
+                            builder.bind_index_buffer(indices)?;
+                            unsafe { builder.draw_indexed(index_count, 1, 0, 0, 0) }?;
+                        }
+                        None => {
+                            unsafe { builder.draw(model_data.positions().len() as u32, 1, 0, 0) }?;
+                        }
+                    }
+                }
+            }
+
+            builder.end_render_pass(SubpassEndInfo::default())?;
+
+            Ok(())
+        });
+
+        Ok(recorder)
+    });
+
+    Ok(())
+}
+
+/// Scales `source` to fit `target`
+fn add_upscale_pass(builder: &mut RenderGraphBuilder<FrameData>, source: ImageId, target: ImageId, filter: Filter) {
+    let accesses = [(source, ImageAccess::TransferSrc), (target, ImageAccess::TransferDst)];
+
+    builder.add_pass("upscale", &accesses, move |_images, _transitions| {
+        let recorder: PassRecorder<FrameData> = Box::new(move |builder, images, _frame| {
+            builder.blit_image(BlitImageInfo {
+                filter,
+                ..BlitImageInfo::images(images.get(source)?.clone(), images.get(target)?.clone())
+            })?;
+
+            Ok(())
+        });
+
+        Ok(recorder)
+    });
+}
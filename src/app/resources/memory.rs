@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::memory::allocator::{
+    AllocationCreateInfo, AllocationType, MemoryAlloc, MemoryAllocator, MemoryAllocatorError,
+    MemoryTypeFilter, StandardMemoryAllocator, Suballocator, SuballocationCreateInfo,
+};
+use vulkano::memory::{DedicatedAllocation, ExternalMemoryHandleTypes, MemoryHeapFlags, MemoryRequirements};
+use vulkano::DeviceSize;
+
+/// How much of a memory heap the engine's allocator is using
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    pub heap_index: u32,
+    /// The size of the whole heap, shared with everything else on the system
+    pub size: u64,
+    pub device_local: bool,
+    /// Memory the allocator has taken from the heap, in blocks and dedicated allocations
+    pub reserved: u64,
+    /// The part of `reserved` handed out to buffers and images
+    pub used: u64,
+    /// The part of `used` in dedicated allocations, which large resources get instead of a block
+    pub dedicated: u64,
+    pub allocation_count: usize,
+}
+
+impl HeapStats {
+    /// Stats for every heap of the allocator's device
+    pub(crate) fn collect(memory_allocator: &TrackingMemoryAllocator) -> Vec<HeapStats> {
+        let memory_properties = memory_allocator.device().physical_device().memory_properties();
+
+        let mut stats: Vec<_> = memory_properties.memory_heaps
+            .iter()
+            .zip(memory_allocator.dedicated.iter())
+            .enumerate()
+            .map(|(heap_index, (heap, dedicated))| {
+                let dedicated_size = dedicated.size.load(Ordering::Relaxed);
+
+                HeapStats {
+                    heap_index: heap_index as u32,
+                    size: heap.size,
+                    device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+                    reserved: dedicated_size,
+                    used: dedicated_size,
+                    dedicated: dedicated_size,
+                    allocation_count: dedicated.count.load(Ordering::Relaxed),
+                }
+            })
+            .collect();
+
+        for (memory_type, pool) in memory_properties.memory_types.iter().zip(memory_allocator.allocator.pools()) {
+            let heap_stats = &mut stats[memory_type.heap_index as usize];
+
+            for block in pool.blocks() {
+                let block_size = block.device_memory().allocation_size();
+
+                heap_stats.reserved += block_size;
+                heap_stats.used += block_size - block.suballocator().free_size();
+                heap_stats.allocation_count += block.allocation_count();
+            }
+        }
+
+        stats
+    }
+}
+
+/// Dedicated allocations on one heap
+#[derive(Default)]
+struct DedicatedUsage {
+    size: AtomicU64,
+    count: AtomicUsize,
+}
+
+/// The standard allocator, also counting the dedicated allocations it makes outside its blocks
+/// so `HeapStats` can include them
+pub struct TrackingMemoryAllocator {
+    allocator: StandardMemoryAllocator,
+    // Indexed by heap
+    dedicated: Vec<DedicatedUsage>,
+}
+
+impl TrackingMemoryAllocator {
+    pub(crate) fn new(device: Arc<Device>) -> Self {
+        let heap_count = device.physical_device().memory_properties().memory_heaps.len();
+
+        Self {
+            allocator: StandardMemoryAllocator::new_default(device),
+            dedicated: (0..heap_count).map(|_| DedicatedUsage::default()).collect(),
+        }
+    }
+
+    /// The dedicated usage of the heap `allocation` is on, `None` if it was suballocated from a block
+    fn dedicated_usage(&self, allocation: &MemoryAlloc) -> Option<&DedicatedUsage> {
+        if allocation.suballocation.is_some() {
+            return None;
+        }
+
+        let memory_types = &self.device().physical_device().memory_properties().memory_types;
+        let heap_index = memory_types[allocation.device_memory.memory_type_index() as usize].heap_index;
+
+        self.dedicated.get(heap_index as usize)
+    }
+
+    fn track(&self, allocation: &MemoryAlloc) {
+        if let Some(usage) = self.dedicated_usage(allocation) {
+            usage.size.fetch_add(allocation.device_memory.allocation_size(), Ordering::Relaxed);
+            usage.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn untrack(&self, allocation: &MemoryAlloc) {
+        if let Some(usage) = self.dedicated_usage(allocation) {
+            usage.size.fetch_sub(allocation.device_memory.allocation_size(), Ordering::Relaxed);
+            usage.count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl DeviceOwned for TrackingMemoryAllocator {
+    fn device(&self) -> &Arc<Device> {
+        self.allocator.device()
+    }
+}
+
+unsafe impl MemoryAllocator for TrackingMemoryAllocator {
+    fn find_memory_type_index(&self, memory_type_bits: u32, filter: MemoryTypeFilter) -> Option<u32> {
+        self.allocator.find_memory_type_index(memory_type_bits, filter)
+    }
+
+    fn allocate_from_type(
+        &self,
+        memory_type_index: u32,
+        create_info: SuballocationCreateInfo,
+    ) -> Result<MemoryAlloc, MemoryAllocatorError> {
+        self.allocator
+            .allocate_from_type(memory_type_index, create_info)
+            .inspect(|allocation| self.track(allocation))
+    }
+
+    fn allocate(
+        &self,
+        requirements: MemoryRequirements,
+        allocation_type: AllocationType,
+        create_info: AllocationCreateInfo,
+        dedicated_allocation: Option<DedicatedAllocation<'_>>,
+    ) -> Result<MemoryAlloc, MemoryAllocatorError> {
+        self.allocator
+            .allocate(requirements, allocation_type, create_info, dedicated_allocation)
+            .inspect(|allocation| self.track(allocation))
+    }
+
+    fn allocate_dedicated(
+        &self,
+        memory_type_index: u32,
+        allocation_size: DeviceSize,
+        dedicated_allocation: Option<DedicatedAllocation<'_>>,
+        export_handle_types: ExternalMemoryHandleTypes,
+    ) -> Result<MemoryAlloc, MemoryAllocatorError> {
+        self.allocator
+            .allocate_dedicated(memory_type_index, allocation_size, dedicated_allocation, export_handle_types)
+            .inspect(|allocation| self.track(allocation))
+    }
+
+    unsafe fn deallocate(&self, allocation: MemoryAlloc) {
+        self.untrack(&allocation);
+        unsafe { self.allocator.deallocate(allocation) }
+    }
+}
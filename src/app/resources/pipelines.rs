@@ -0,0 +1,150 @@
+use crate::app::shaders::IcarusShader;
+use crate::render::PipelineKey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use vulkano::buffer::BufferContents;
+use vulkano::format::Format;
+use vulkano::image::{ImageLayout, SampleCount};
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::{IntoPipelineLayoutCreateInfoError, PipelineDescriptorSetLayoutCreateInfo};
+use vulkano::pipeline::{DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::render_pass::{RenderPass, Subpass};
+use vulkano::{Validated, VulkanError};
+
+#[derive(Error, Debug)]
+pub enum PipelineError {
+    #[error("shader entry point {0} does not exist")]
+    MissingEntryPoint(&'static str),
+    #[error("failed to create pipeline layout! {0}")]
+    LayoutError(#[from] IntoPipelineLayoutCreateInfoError),
+    #[error("vulkan error! {0}")]
+    VulkanError(#[from] Validated<VulkanError>),
+}
+
+/// Must match `MaterialConstants` in `icarus-shaders`
+#[derive(BufferContents, Copy, Clone)]
+#[repr(C)]
+pub struct MaterialConstants {
+    pub base_color: [f32; 4],
+}
+
+/// Graphics pipelines for a single subpass, built on first use
+pub struct PipelineCache {
+    subpass: Subpass,
+    pipelines: HashMap<PipelineKey, Arc<GraphicsPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new(subpass: Subpass) -> Self {
+        Self {
+            subpass,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub fn render_pass(&self) -> &Arc<RenderPass> {
+        self.subpass.render_pass()
+    }
+
+    pub fn get_or_create(&mut self, shader: &IcarusShader, key: &PipelineKey) -> Result<Arc<GraphicsPipeline>, PipelineError> {
+        if let Some(pipeline) = self.pipelines.get(key) {
+            return Ok(pipeline.clone());
+        }
+
+        let pipeline = self.create(shader, key)?;
+        self.pipelines.insert(key.clone(), pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    fn create(&self, shader: &IcarusShader, key: &PipelineKey) -> Result<Arc<GraphicsPipeline>, PipelineError> {
+        let device = shader.device();
+
+        let vertex_shader = shader
+            .entry_point(key.vertex_shader)
+            .ok_or(PipelineError::MissingEntryPoint(key.vertex_shader))?;
+        let fragment_shader = shader
+            .entry_point(key.fragment_shader)
+            .ok_or(PipelineError::MissingEntryPoint(key.fragment_shader))?;
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex_shader),
+            PipelineShaderStageCreateInfo::new(fragment_shader),
+        ];
+
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())?,
+        )?;
+
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(key.vertex_layout.vertex_input_state()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: self.subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                    ..MultisampleState::default()
+                }),
+                depth_stencil_state: self.subpass.has_depth().then(|| DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..DepthStencilState::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    self.subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )?;
+
+        Ok(pipeline)
+    }
+}
+
+/// Identifies a render pass the frame graph draws in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum RenderPassKey {
+    Scene { samples: SampleCount, final_layout: ImageLayout },
+    FullScreen { format: Format, final_layout: ImageLayout },
+}
+
+/// A pipeline cache for each render pass, kept with the device so rebuilding a frame graph on resize
+/// or a settings change doesn't recompile every pipeline. Clones share the same caches
+#[derive(Clone, Default)]
+pub(crate) struct PipelineCaches {
+    caches: Arc<Mutex<HashMap<RenderPassKey, Arc<Mutex<PipelineCache>>>>>,
+}
+
+impl PipelineCaches {
+    /// The cache for `key`, creating its render pass with `render_pass` the first time
+    pub fn get_or_create<E, F>(&self, key: RenderPassKey, render_pass: F) -> Result<Arc<Mutex<PipelineCache>>, E>
+    where
+        F: FnOnce() -> Result<Arc<RenderPass>, E>,
+    {
+        let mut caches = self.caches.lock().unwrap();
+
+        if let Some(cache) = caches.get(&key) {
+            return Ok(cache.clone());
+        }
+
+        let cache = Arc::new(Mutex::new(PipelineCache::new(Subpass::from(render_pass()?, 0).unwrap())));
+        caches.insert(key, cache.clone());
+
+        Ok(cache)
+    }
+}
@@ -0,0 +1,225 @@
+use crate::app::resources::frame_graph::{FrameData, GraphContext};
+use crate::app::resources::RenderPassKey;
+use crate::app::settings::{PostEffect, PostProcessPass};
+use crate::render::{ImageAccess, ImageDescription, ImageId, PassRecorder, PipelineKey, RenderGraphBuilder, RenderGraphError, VertexLayout};
+use log::debug;
+use std::sync::Arc;
+use vulkano::buffer::BufferContents;
+use vulkano::command_buffer::{RenderPassBeginInfo, SubpassBeginInfo, SubpassContents, SubpassEndInfo};
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::DeviceOwned;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Sampler, SamplerCreateInfo};
+use vulkano::image::{ImageLayout, ImageUsage, SampleCount};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp, Framebuffer, FramebufferCreateInfo, RenderPass, RenderPassCreateInfo, SubpassDependency, SubpassDescription};
+
+/// Must match `PostProcessConstants` in `icarus-shaders`
+#[derive(BufferContents, Copy, Clone)]
+#[repr(C)]
+struct PostProcessConstants {
+    texel_size: [f32; 2],
+    strength: f32,
+    _padding: f32,
+}
+
+impl PostEffect {
+    fn fragment_shader(&self) -> &'static str {
+        match self {
+            PostEffect::Tonemap => "tonemap_fs",
+            PostEffect::Gamma => "gamma_fs",
+            PostEffect::Vignette => "vignette_fs",
+            PostEffect::Fxaa => "fxaa_fs",
+        }
+    }
+
+    fn pass_name(&self) -> &'static str {
+        match self {
+            PostEffect::Tonemap => "tonemap",
+            PostEffect::Gamma => "gamma",
+            PostEffect::Vignette => "vignette",
+            PostEffect::Fxaa => "fxaa",
+        }
+    }
+}
+
+/// The brightness in nits an HDR10 display shows for 1.0, the reference white of BT.2408
+const HDR10_WHITE_NITS: f32 = 203.0;
+
+/// Adds the enabled passes of `chain` after `source`, each drawing into a new image. Gamma is skipped
+/// when `is_encoded`, as the output is already encoded when written. Returns the image holding the final result
+pub(crate) fn add_post_process_chain(
+    builder: &mut RenderGraphBuilder<FrameData>,
+    context: &GraphContext,
+    chain: &[PostProcessPass],
+    is_encoded: bool,
+    source: ImageId,
+) -> Result<ImageId, RenderGraphError> {
+    chain
+        .iter()
+        .filter(|pass| pass.enabled)
+        .filter(|pass| {
+            let is_skipped = is_encoded && pass.effect == PostEffect::Gamma;
+            if is_skipped {
+                debug!("Skipping the gamma pass, the swapchain encodes its own output");
+            }
+            !is_skipped
+        })
+        .try_fold(source, |source, pass| add_post_process_pass(builder, context, *pass, source))
+}
+
+/// Encodes `source` the way an HDR10 swapchain expects, which no image format does on write.
+/// Returns the encoded image
+pub(crate) fn add_hdr10_encode_pass(
+    builder: &mut RenderGraphBuilder<FrameData>,
+    context: &GraphContext,
+    source: ImageId,
+) -> Result<ImageId, RenderGraphError> {
+    add_full_screen_pass(builder, context, "hdr10_encode", "pq_encode_fs", HDR10_WHITE_NITS, source)
+}
+
+fn add_post_process_pass(
+    builder: &mut RenderGraphBuilder<FrameData>,
+    context: &GraphContext,
+    pass: PostProcessPass,
+    source: ImageId,
+) -> Result<ImageId, RenderGraphError> {
+    add_full_screen_pass(builder, context, pass.effect.pass_name(), pass.effect.fragment_shader(), pass.strength, source)
+}
+
+/// Draws `fragment_shader` over the whole of a new image with `source` bound as its texture
+fn add_full_screen_pass(
+    builder: &mut RenderGraphBuilder<FrameData>,
+    context: &GraphContext,
+    name: &'static str,
+    fragment_shader: &'static str,
+    strength: f32,
+    source: ImageId,
+) -> Result<ImageId, RenderGraphError> {
+    let description = builder.description(source)?;
+    let format = description.format;
+    let extent = description.extent;
+
+    let target = builder.create_image(name, ImageDescription {
+        format,
+        extent,
+        samples: SampleCount::Sample1,
+        usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+    });
+
+    let context = context.clone();
+
+    let accesses = [(source, ImageAccess::Sampled), (target, ImageAccess::ColorAttachment)];
+
+    builder.add_pass(name, &accesses, move |images, transitions| {
+        let device = context.memory_allocator.device().clone();
+
+        let final_layout = transitions.get(target)?.final_layout();
+        let pipeline_cache = context.pipelines.get_or_create(RenderPassKey::FullScreen { format, final_layout }, || {
+            full_screen_render_pass(&context, format, final_layout, transitions.dependencies())
+        })?;
+
+        let (render_pass, pipeline) = {
+            let mut pipeline_cache = pipeline_cache.lock().unwrap();
+            let key = PipelineKey {
+                vertex_shader: "fullscreen_vs",
+                fragment_shader,
+                vertex_layout: VertexLayout::None,
+            };
+            let pipeline = pipeline_cache
+                .get_or_create(&context.shader, &key)
+                .map_err(|error| RenderGraphError::PassError { pass: name, error: Box::new(error) })?;
+
+            (pipeline_cache.render_pass().clone(), pipeline)
+        };
+
+        let frame_buffer = Framebuffer::new(
+            render_pass,
+            FramebufferCreateInfo {
+                attachments: vec![images.view(target)?],
+                ..FramebufferCreateInfo::default()
+            },
+        )?;
+
+        let sampler = Sampler::new(device, SamplerCreateInfo::simple_repeat_linear_no_mipmap())?;
+
+        let descriptor_set = DescriptorSet::new(
+            context.descriptor_set_allocator.clone(),
+            pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(0, images.view(source)?),
+                WriteDescriptorSet::sampler(1, sampler),
+            ],
+            [],
+        )?;
+
+        let constants = PostProcessConstants {
+            texel_size: [1.0 / extent[0] as f32, 1.0 / extent[1] as f32],
+            strength,
+            _padding: 0.0,
+        };
+
+        let recorder: PassRecorder<FrameData> = Box::new(move |builder, _images, _frame| {
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: vec![None],
+                        ..RenderPassBeginInfo::framebuffer(frame_buffer.clone())
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::Inline,
+                        ..Default::default()
+                    },
+                )?
+                .set_viewport(0, [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [extent[0] as f32, extent[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }].into_iter().collect())?
+                .bind_pipeline_graphics(pipeline.clone())?
+                .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set.clone())?
+                .push_constants(pipeline.layout().clone(), 0, constants)?;
+
+            unsafe { builder.draw(3, 1, 0, 0) }?;
+
+            builder.end_render_pass(SubpassEndInfo::default())?;
+
+            Ok(())
+        });
+
+        Ok(recorder)
+    });
+
+    Ok(target)
+}
+
+fn full_screen_render_pass(
+    context: &GraphContext,
+    format: Format,
+    final_layout: ImageLayout,
+    dependencies: Vec<SubpassDependency>,
+) -> Result<Arc<RenderPass>, RenderGraphError> {
+    Ok(RenderPass::new(context.memory_allocator.device().clone(), RenderPassCreateInfo {
+        attachments: vec![AttachmentDescription {
+            format,
+            samples: SampleCount::Sample1,
+            // Every pixel is overwritten so there's nothing to load
+            load_op: AttachmentLoadOp::DontCare,
+            store_op: AttachmentStoreOp::Store,
+            initial_layout: ImageLayout::Undefined,
+            final_layout,
+            ..Default::default()
+        }],
+        subpasses: vec![SubpassDescription {
+            color_attachments: vec![Some(AttachmentReference {
+                attachment: 0,
+                layout: ImageLayout::ColorAttachmentOptimal,
+                ..Default::default()
+            })],
+            ..Default::default()
+        }],
+        dependencies,
+        ..Default::default()
+    })?)
+}
@@ -0,0 +1,120 @@
+use crate::app::resources::ResourceError;
+use std::sync::Arc;
+use vulkano::device::Device;
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::{ImageLayout, SampleCount};
+use vulkano::render_pass::{AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp, RenderPass, RenderPassCreateInfo, SubpassDependency, SubpassDescription};
+
+/// Describes the render pass the scene is drawn in
+#[derive(Clone, Debug)]
+pub struct RenderPassConfig {
+    /// The depth attachment format, or `None` to draw without depth testing
+    pub depth_format: Option<Format>,
+    pub clear_color: [f32; 4],
+    pub clear_depth: f32,
+}
+
+impl Default for RenderPassConfig {
+    fn default() -> Self {
+        Self {
+            depth_format: Some(Format::D32_SFLOAT),
+            clear_color: [0.1, 0.1, 0.1, 1.0],
+            clear_depth: 1.0,
+        }
+    }
+}
+
+impl RenderPassConfig {
+    /// Builds the render pass, any `samples` above one is resolved into a single sampled image which is left
+    /// in `final_layout`. Attachments are ordered color, depth, then resolve
+    pub(crate) fn render_pass(
+        &self,
+        color_format: Format,
+        samples: SampleCount,
+        final_layout: ImageLayout,
+        dependencies: Vec<SubpassDependency>,
+        device: Arc<Device>,
+    ) -> Result<Arc<RenderPass>, ResourceError> {
+        let is_multisampled = samples != SampleCount::Sample1;
+
+        let mut attachments = vec![AttachmentDescription {
+            format: color_format,
+            samples,
+            load_op: AttachmentLoadOp::Clear,
+            // Multisampled color only lives until it is resolved
+            store_op: if is_multisampled { AttachmentStoreOp::DontCare } else { AttachmentStoreOp::Store },
+            initial_layout: ImageLayout::Undefined,
+            final_layout: if is_multisampled { ImageLayout::ColorAttachmentOptimal } else { final_layout },
+            ..Default::default()
+        }];
+
+        let depth_stencil_attachment = self.depth_format.map(|format| {
+            attachments.push(AttachmentDescription {
+                format,
+                samples,
+                load_op: AttachmentLoadOp::Clear,
+                store_op: AttachmentStoreOp::DontCare,
+                initial_layout: ImageLayout::Undefined,
+                final_layout: ImageLayout::DepthStencilAttachmentOptimal,
+                ..Default::default()
+            });
+
+            AttachmentReference {
+                attachment: attachments.len() as u32 - 1,
+                layout: ImageLayout::DepthStencilAttachmentOptimal,
+                ..Default::default()
+            }
+        });
+
+        let color_resolve_attachments = if is_multisampled {
+            attachments.push(AttachmentDescription {
+                format: color_format,
+                samples: SampleCount::Sample1,
+                load_op: AttachmentLoadOp::DontCare,
+                store_op: AttachmentStoreOp::Store,
+                initial_layout: ImageLayout::Undefined,
+                final_layout,
+                ..Default::default()
+            });
+
+            vec![Some(AttachmentReference {
+                attachment: attachments.len() as u32 - 1,
+                layout: ImageLayout::ColorAttachmentOptimal,
+                ..Default::default()
+            })]
+        } else {
+            Vec::new()
+        };
+
+        let render_pass = RenderPass::new(device, RenderPassCreateInfo {
+            attachments,
+            subpasses: vec![SubpassDescription {
+                color_attachments: vec![Some(AttachmentReference {
+                    attachment: 0,
+                    layout: ImageLayout::ColorAttachmentOptimal,
+                    ..Default::default()
+                })],
+                color_resolve_attachments,
+                depth_stencil_attachment,
+                ..Default::default()
+            }],
+            dependencies,
+            ..Default::default()
+        })?;
+
+        Ok(render_pass)
+    }
+
+    /// One clear value per attachment of the render pass built with `samples`
+    pub(crate) fn clear_values(&self, samples: SampleCount) -> Vec<Option<ClearValue>> {
+        let mut clear_values = vec![Some(self.clear_color.into())];
+        if self.depth_format.is_some() {
+            clear_values.push(Some(self.clear_depth.into()));
+        }
+        if samples != SampleCount::Sample1 {
+            clear_values.push(None);
+        }
+
+        clear_values
+    }
+}
@@ -12,6 +12,12 @@ pub const REQUIRED_INSTANCE_EXTENSIONS: InstanceExtensions = InstanceExtensions
     ..InstanceExtensions::empty()
 };
 
+/// Enabled when available, the HDR color spaces are only reported with `ext_swapchain_colorspace`
+pub const OPTIONAL_INSTANCE_EXTENSIONS: InstanceExtensions = InstanceExtensions {
+    ext_swapchain_colorspace: true,
+    ..InstanceExtensions::empty()
+};
+
 const REQUIRED_LAYERS: &[&str] = &[
     #[cfg(debug_assertions)]
     "VK_LAYER_KHRONOS_validation",
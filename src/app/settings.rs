@@ -1,15 +1,281 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use vulkano::image::sampler::Filter;
+use vulkano::image::SampleCount;
+use vulkano::swapchain::PresentMode;
+
+/// Past this the scene costs more than rendering at a larger window would
+const MAX_RENDER_SCALE: f32 = 4.0;
+
+/// Multisample anti-aliasing level
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Msaa {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa {
+    pub fn sample_count(&self) -> SampleCount {
+        match self {
+            Msaa::Off => SampleCount::Sample1,
+            Msaa::X2 => SampleCount::Sample2,
+            Msaa::X4 => SampleCount::Sample4,
+            Msaa::X8 => SampleCount::Sample8,
+        }
+    }
+}
+
+/// How the rendered frame is scaled to fit the window
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpscaleFilter {
+    /// Keeps hard pixel edges, for pixel art
+    Nearest,
+    Linear,
+}
+
+impl From<UpscaleFilter> for Filter {
+    fn from(value: UpscaleFilter) -> Self {
+        match value {
+            UpscaleFilter::Nearest => Filter::Nearest,
+            UpscaleFilter::Linear => Filter::Linear,
+        }
+    }
+}
+
+/// A full screen effect applied to the rendered scene
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostEffect {
+    /// Maps HDR color into displayable range, `strength` is the exposure
+    Tonemap,
+    /// `strength` is the display gamma, skipped when the swapchain is sRGB or HDR10 and encodes its own output
+    Gamma,
+    /// Darkens the edges of the screen by `strength`
+    Vignette,
+    /// Fast approximate anti-aliasing
+    Fxaa,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct PostProcessPass {
+    pub effect: PostEffect,
+    pub enabled: bool,
+    pub strength: f32,
+}
+
+impl PostProcessPass {
+    pub fn new(effect: PostEffect) -> Self {
+        let strength = match effect {
+            PostEffect::Tonemap => 1.0,
+            PostEffect::Gamma => 2.2,
+            PostEffect::Vignette => 0.5,
+            PostEffect::Fxaa => 1.0,
+        };
+
+        Self {
+            effect,
+            enabled: true,
+            strength,
+        }
+    }
+}
+
+/// The color space frames are presented in, falls back to `Srgb` when the display can't do it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisplayColorSpace {
+    Srgb,
+    /// HDR10, the final image is PQ encoded in BT.2020 primaries before presenting
+    Hdr10,
+    /// scRGB, linear values with anything above 1.0 brighter than SDR white
+    ExtendedSrgb,
+}
+
+/// How presenting waits for the display, falls back to `On` when the preferred mode is unsupported
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Vsync {
+    /// Presents immediately and may tear
+    Off,
+    On,
+    /// Waits for the display unless a frame is late, which then tears instead of stuttering
+    Relaxed,
+    /// Never tears but replaces queued frames with newer ones instead of waiting
+    Mailbox,
+}
+
+impl Vsync {
+    /// Present modes in order of preference, ending with the one every device supports
+    pub fn present_modes(&self) -> &'static [PresentMode] {
+        match self {
+            Vsync::Off => &[PresentMode::Immediate, PresentMode::Mailbox, PresentMode::Fifo],
+            Vsync::On => &[PresentMode::Fifo],
+            Vsync::Relaxed => &[PresentMode::FifoRelaxed, PresentMode::Fifo],
+            Vsync::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+        }
+    }
+}
+
+/// How the window occupies its monitor
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowMode {
+    Windowed,
+    /// A borderless window covering the monitor at its desktop resolution
+    Borderless,
+    /// Takes over the monitor with the closest video mode, either field left `None` picks the largest.
+    /// Falls back to `Borderless` when the monitor has no matching mode
+    Exclusive {
+        size: Option<[u32; 2]>,
+        refresh_rate_millihertz: Option<u32>,
+    },
+}
+
+/// How the cursor is held by the window
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CursorGrab {
+    None,
+    /// Kept inside the window
+    Confined,
+    /// Kept in place, for mouse look. Falls back to `Confined` where the platform can't lock
+    Locked,
+}
+
+/// The primary window, applied when it is created and kept up to date by `WindowManager`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    /// The inner size in logical pixels when windowed
+    pub size: [u32; 2],
+    pub min_size: Option<[u32; 2]>,
+    pub max_size: Option<[u32; 2]>,
+    /// The outer position on the desktop, `None` lets the platform choose
+    pub position: Option<[i32; 2]>,
+    pub mode: WindowMode,
+    /// Index into the available monitors for fullscreen, `None` is the primary monitor
+    pub monitor: Option<usize>,
+    pub resizable: bool,
+    pub decorations: bool,
+    pub cursor_grab: CursorGrab,
+    pub cursor_visible: bool,
+    /// An image shown in the title bar and task bar
+    pub icon: Option<PathBuf>,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            size: [1920, 1080],
+            min_size: None,
+            max_size: None,
+            position: None,
+            mode: WindowMode::Windowed,
+            monitor: None,
+            resizable: true,
+            decorations: true,
+            cursor_grab: CursorGrab::None,
+            cursor_visible: true,
+            icon: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub render_size: [u32; 2],
-    pub window_size: [u32; 2],
+    /// When set, the scene is rendered at this fraction of the window size instead of `render_size`
+    pub render_scale: Option<f32>,
+    pub upscale_filter: UpscaleFilter,
+    pub window: WindowSettings,
+    /// The `(vendor_id, device_id)` to use when it's suitable, overridden by `--device` or `ICARUS_DEVICE`
     pub preferred_device: Option<(u32, u32)>,
+    /// Clamped to the highest level the device supports
+    pub msaa: Msaa,
+    /// Applied in order to the rendered scene before it is scaled to the window
+    pub post_processing: Vec<PostProcessPass>,
+    pub color_space: DisplayColorSpace,
+    pub vsync: Vsync,
+    /// Limits frames per second whenever presenting doesn't wait for the display
+    pub frame_rate_cap: Option<u32>,
+    /// Limits frames per second while no window has focus, `None` keeps drawing as usual
+    pub unfocused_frame_rate: Option<u32>,
+}
+
+impl Settings {
+    /// The size the scene is rendered at for a window of `window_size`
+    pub fn render_extent(&self, window_size: [u32; 2]) -> [u32; 2] {
+        match self.render_scale {
+            Some(scale) => window_size.map(|x| ((x as f32 * scale).round() as u32).max(1)),
+            None => self.render_size,
+        }
+    }
+
+    /// Replaces values that can't be used, such as those from a hand edited settings file, warning about each
+    pub fn validate(&mut self) {
+        let defaults = Settings::default();
+
+        if self.render_size.contains(&0) {
+            warn!("Render size {:?} is empty, using {:?}", self.render_size, defaults.render_size);
+            self.render_size = defaults.render_size;
+        }
+
+        if let Some(scale) = self.render_scale.filter(|scale| !scale.is_finite() || *scale <= 0.0 || *scale > MAX_RENDER_SCALE) {
+            warn!("Render scale {scale} is out of range, rendering at the render size instead");
+            self.render_scale = None;
+        }
+
+        if self.frame_rate_cap == Some(0) {
+            warn!("A frame rate cap of 0 would never draw, uncapping it");
+            self.frame_rate_cap = None;
+        }
+
+        if self.unfocused_frame_rate == Some(0) {
+            warn!("An unfocused frame rate of 0 would never draw, uncapping it");
+            self.unfocused_frame_rate = None;
+        }
+
+        for pass in self.post_processing.iter_mut().filter(|pass| !pass.strength.is_finite()) {
+            let strength = PostProcessPass::new(pass.effect).strength;
+            warn!("{:?} strength {} is not a number, using {strength}", pass.effect, pass.strength);
+            pass.strength = strength;
+        }
+
+        self.window.validate();
+    }
+}
+
+impl WindowSettings {
+    fn validate(&mut self) {
+        let defaults = WindowSettings::default();
+
+        if self.size.contains(&0) {
+            warn!("Window size {:?} is empty, using {:?}", self.size, defaults.size);
+            self.size = defaults.size;
+        }
+
+        if let (Some(min_size), Some(max_size)) = (self.min_size, self.max_size) {
+            if min_size[0] > max_size[0] || min_size[1] > max_size[1] {
+                warn!("Window min size {min_size:?} is larger than the max size {max_size:?}, ignoring the max size");
+                self.max_size = None;
+            }
+        }
+    }
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             render_size: [1920, 1080],
-            window_size: [1920, 1080],
+            render_scale: None,
+            upscale_filter: UpscaleFilter::Linear,
+            window: WindowSettings::default(),
             preferred_device: None,
+            msaa: Msaa::Off,
+            post_processing: Vec::new(),
+            color_space: DisplayColorSpace::Srgb,
+            vsync: Vsync::On,
+            frame_rate_cap: None,
+            unfocused_frame_rate: None,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,155 @@
+use crate::app::settings::Settings;
+use log::{debug, info, warn};
+use ron::ser::PrettyConfig;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const SETTINGS_FILE_NAME: &str = "settings.ron";
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("failed to access settings file {}! {error}", path.display())]
+    IoError {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("failed to parse settings file {}! {error}", path.display())]
+    ParseError {
+        path: PathBuf,
+        error: ron::error::SpannedError,
+    },
+    #[error("failed to write settings section {section}! {error}")]
+    SerializeError {
+        section: String,
+        error: ron::Error,
+    },
+    #[error("failed to read settings section {section}! {error}")]
+    SectionError {
+        section: String,
+        error: ron::Error,
+    },
+    #[error("this platform has no config directory")]
+    NoConfigDir,
+}
+
+/// What is actually stored on disk, sections are kept as RON text so each is only parsed as its own type
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct SettingsFileContents {
+    settings: Settings,
+    sections: BTreeMap<String, String>,
+}
+
+/// Settings stored as RON in the platform config directory, alongside any sections the game adds.
+/// Missing fields fall back to `Settings::default()`
+pub struct SettingsFile {
+    path: PathBuf,
+    // The RON text of each section
+    sections: BTreeMap<String, String>,
+    // Set when the settings were overridden or the file failed to parse, so forced values
+    // or defaults never replace what the user wrote
+    read_only: bool,
+}
+
+impl SettingsFile {
+    /// The settings file for `app_name` in the platform config directory, such as `~/.config/<app_name>/settings.ron`
+    pub fn default_path(app_name: &str) -> Result<PathBuf, SettingsError> {
+        let config_dir = dirs::config_dir().ok_or(SettingsError::NoConfigDir)?;
+
+        Ok(config_dir.join(app_name).join(SETTINGS_FILE_NAME))
+    }
+
+    /// Reads the settings at `path`, a missing file gives the defaults. A file that fails to parse
+    /// also gives the defaults, since refusing to start over a bad edit helps nobody, but is left
+    /// read only so the edit isn't lost on exit
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(Self, Settings), SettingsError> {
+        let path = path.as_ref().to_path_buf();
+        let mut read_only = false;
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(text) => match ron::from_str::<SettingsFileContents>(&text) {
+                Ok(contents) => {
+                    info!("Loaded settings from {}", path.display());
+                    contents
+                }
+                Err(error) => {
+                    warn!("{}, using the default settings and leaving the file untouched", SettingsError::ParseError { path: path.clone(), error });
+                    read_only = true;
+                    SettingsFileContents::default()
+                }
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                debug!("No settings file at {}, using the default settings", path.display());
+                SettingsFileContents::default()
+            }
+            Err(error) => return Err(SettingsError::IoError { path, error }),
+        };
+
+        let mut settings = contents.settings;
+        settings.validate();
+
+        Ok((Self { path, sections: contents.sections, read_only }, settings))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stops `save` from writing anything
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// A section the game stored, its default when there is none yet
+    pub fn section<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T, SettingsError> {
+        match self.sections.get(name) {
+            Some(text) => ron::from_str(text)
+                .map_err(|error| SettingsError::SectionError { section: name.to_string(), error: error.code }),
+            None => Ok(T::default()),
+        }
+    }
+
+    /// Stores a section of the game's own settings, written out on the next `save`
+    pub fn set_section<T: Serialize>(&mut self, name: &str, section: &T) -> Result<(), SettingsError> {
+        let text = ron::to_string(section)
+            .map_err(|error| SettingsError::SerializeError { section: name.to_string(), error })?;
+
+        self.sections.insert(name.to_string(), text);
+
+        Ok(())
+    }
+
+    /// Writes `settings` and every section, creating the config directory if needed.
+    /// The file is written beside the old one and renamed over it, so a crash mid write can't corrupt it
+    pub fn save(&self, settings: &Settings) -> Result<(), SettingsError> {
+        if self.read_only {
+            debug!("Settings file {} is read only, not saving", self.path.display());
+            return Ok(());
+        }
+
+        let contents = SettingsFileContents {
+            settings: settings.clone(),
+            sections: self.sections.clone(),
+        };
+
+        let text = ron::ser::to_string_pretty(&contents, PrettyConfig::default())
+            .map_err(|error| SettingsError::SerializeError { section: String::from("settings"), error })?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|error| SettingsError::IoError { path: parent.to_path_buf(), error })?;
+        }
+
+        let temp_path = self.path.with_extension("ron.tmp");
+        fs::write(&temp_path, text).map_err(|error| SettingsError::IoError { path: temp_path.clone(), error })?;
+        fs::rename(&temp_path, &self.path).map_err(|error| SettingsError::IoError { path: self.path.clone(), error })?;
+
+        info!("Saved settings to {}", self.path.display());
+
+        Ok(())
+    }
+}
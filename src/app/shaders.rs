@@ -1,6 +1,6 @@
 use std::sync::Arc;
-use vulkano::device::Device;
-use vulkano::shader::{ShaderModule, ShaderModuleCreateInfo};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::shader::{EntryPoint, ShaderModule, ShaderModuleCreateInfo};
 use vulkano::{Validated, VulkanError};
 
 const SHADER_MODULE_BIN: &[u32] = &{
@@ -24,7 +24,8 @@ const SHADER_MODULE_BIN: &[u32] = &{
     u32_buffer
 };
 
-struct IcarusShader {
+#[derive(Clone)]
+pub(crate) struct IcarusShader {
     shader_module: Arc<ShaderModule>,
 }
 
@@ -33,9 +34,11 @@ impl IcarusShader {
         unsafe { ShaderModule::new(logical_device, ShaderModuleCreateInfo::new(SHADER_MODULE_BIN)) }.map(|shader_module| IcarusShader { shader_module })
     }
 
-    pub fn graphics_pipeline(&self) {
-        // self.shader_module.entry_point("main")?.module().
-        todo!()
+    pub fn device(&self) -> &Arc<Device> {
+        self.shader_module.device()
     }
-}
 
+    pub fn entry_point(&self, name: &str) -> Option<EntryPoint> {
+        self.shader_module.entry_point(name)
+    }
+}
@@ -0,0 +1,344 @@
+use crate::app::settings::{CursorGrab, WindowMode, WindowSettings};
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use winit::dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize};
+use winit::error::ExternalError;
+use winit::event_loop::ActiveEventLoop;
+use winit::monitor::{MonitorHandle, VideoModeHandle};
+use winit::window::{BadIcon, CursorGrabMode, Fullscreen, Icon, Window, WindowAttributes, WindowId};
+
+#[derive(Error, Debug)]
+pub enum WindowError {
+    #[error("failed to load window icon {}! {error}", path.display())]
+    IconDecodeError {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+    #[error("invalid window icon! {0}")]
+    BadIcon(#[from] BadIcon),
+    #[error("failed to grab cursor! {0}")]
+    CursorGrabError(#[from] ExternalError),
+}
+
+/// Owns the open windows and applies `WindowSettings` to the primary one, keeping the settings in step
+/// with what the user does to it. Settings changed before the primary window exists are stored and
+/// applied when it's created
+pub struct WindowManager {
+    settings: WindowSettings,
+    // The primary window first
+    windows: Vec<Arc<Window>>,
+}
+
+impl WindowManager {
+    pub(crate) fn new(settings: WindowSettings) -> Self {
+        Self {
+            settings,
+            windows: Vec::new(),
+        }
+    }
+
+    /// The primary window's current settings
+    pub fn settings(&self) -> &WindowSettings {
+        &self.settings
+    }
+
+    /// The primary window, `None` until the app has resumed
+    pub fn primary(&self) -> Option<&Arc<Window>> {
+        self.windows.first()
+    }
+
+    pub fn get(&self, window_id: WindowId) -> Option<&Arc<Window>> {
+        self.windows.iter().find(|window| window.id() == window_id)
+    }
+
+    pub fn is_primary(&self, window_id: WindowId) -> bool {
+        self.primary().is_some_and(|window| window.id() == window_id)
+    }
+
+    /// Every monitor, indexed as `WindowSettings::monitor` expects. Empty until the app has resumed
+    pub fn monitors(&self) -> Vec<MonitorHandle> {
+        self.primary()
+            .map(|window| window.available_monitors().collect())
+            .unwrap_or_default()
+    }
+
+    /// The video modes `WindowMode::Exclusive` can choose from on a monitor
+    pub fn video_modes(&self, monitor: usize) -> Vec<VideoModeHandle> {
+        self.monitors()
+            .get(monitor)
+            .map(|monitor| monitor.video_modes().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_mode(&mut self, mode: WindowMode) -> Result<(), WindowError> {
+        self.settings.mode = mode;
+        let Some(window) = self.primary() else {
+            return Ok(());
+        };
+
+        let monitor = select_monitor(window.available_monitors(), window.primary_monitor(), self.settings.monitor);
+        window.set_fullscreen(fullscreen(mode, monitor));
+
+        if mode == WindowMode::Windowed {
+            let _ = window.request_inner_size(LogicalSize::new(self.settings.size[0], self.settings.size[1]));
+        }
+
+        Ok(())
+    }
+
+    /// Moves fullscreen to another monitor, `None` for the primary monitor
+    pub fn set_monitor(&mut self, monitor: Option<usize>) -> Result<(), WindowError> {
+        self.settings.monitor = monitor;
+        self.set_mode(self.settings.mode)
+    }
+
+    /// Resizes the window when windowed, otherwise the size is used once it's windowed again
+    pub fn set_size(&mut self, size: [u32; 2]) -> Result<(), WindowError> {
+        self.settings.size = size;
+        let Some(window) = self.primary() else {
+            return Ok(());
+        };
+
+        if self.settings.mode == WindowMode::Windowed {
+            let _ = window.request_inner_size(LogicalSize::new(size[0], size[1]));
+        }
+
+        Ok(())
+    }
+
+    pub fn set_min_size(&mut self, min_size: Option<[u32; 2]>) -> Result<(), WindowError> {
+        self.settings.min_size = min_size;
+        let Some(window) = self.primary() else {
+            return Ok(());
+        };
+
+        window.set_min_inner_size(min_size.map(|size| LogicalSize::new(size[0], size[1])));
+
+        Ok(())
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<[u32; 2]>) -> Result<(), WindowError> {
+        self.settings.max_size = max_size;
+        let Some(window) = self.primary() else {
+            return Ok(());
+        };
+
+        window.set_max_inner_size(max_size.map(|size| LogicalSize::new(size[0], size[1])));
+
+        Ok(())
+    }
+
+    pub fn set_position(&mut self, position: [i32; 2]) -> Result<(), WindowError> {
+        self.settings.position = Some(position);
+        let Some(window) = self.primary() else {
+            return Ok(());
+        };
+
+        window.set_outer_position(LogicalPosition::new(position[0], position[1]));
+
+        Ok(())
+    }
+
+    pub fn set_resizable(&mut self, resizable: bool) -> Result<(), WindowError> {
+        self.settings.resizable = resizable;
+        if let Some(window) = self.primary() {
+            window.set_resizable(resizable);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_decorations(&mut self, decorations: bool) -> Result<(), WindowError> {
+        self.settings.decorations = decorations;
+        if let Some(window) = self.primary() {
+            window.set_decorations(decorations);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_cursor_grab(&mut self, cursor_grab: CursorGrab) -> Result<(), WindowError> {
+        self.settings.cursor_grab = cursor_grab;
+        let Some(window) = self.primary() else {
+            return Ok(());
+        };
+
+        grab_cursor(window, cursor_grab)
+    }
+
+    pub fn set_cursor_visible(&mut self, cursor_visible: bool) -> Result<(), WindowError> {
+        self.settings.cursor_visible = cursor_visible;
+        if let Some(window) = self.primary() {
+            window.set_cursor_visible(cursor_visible);
+        }
+
+        Ok(())
+    }
+
+    /// Loads `icon` and shows it on the window, `None` restores the platform default
+    pub fn set_icon(&mut self, icon: Option<PathBuf>) -> Result<(), WindowError> {
+        let window_icon = icon.as_deref().map(load_icon).transpose()?;
+        self.settings.icon = icon;
+
+        if let Some(window) = self.primary() {
+            window.set_window_icon(window_icon);
+        }
+
+        Ok(())
+    }
+
+    /// Attributes for the primary window built from the settings
+    pub(crate) fn primary_attributes(&self, event_loop: &ActiveEventLoop) -> WindowAttributes {
+        let settings = &self.settings;
+
+        let mut window_attributes = Window::default_attributes()
+            .with_inner_size(LogicalSize::new(settings.size[0], settings.size[1]))
+            .with_resizable(settings.resizable)
+            .with_decorations(settings.decorations);
+
+        if let Some(min_size) = settings.min_size {
+            window_attributes = window_attributes.with_min_inner_size(LogicalSize::new(min_size[0], min_size[1]));
+        }
+        if let Some(max_size) = settings.max_size {
+            window_attributes = window_attributes.with_max_inner_size(LogicalSize::new(max_size[0], max_size[1]));
+        }
+        if let Some(position) = settings.position {
+            window_attributes = window_attributes.with_position(LogicalPosition::new(position[0], position[1]));
+        }
+
+        let monitor = select_monitor(event_loop.available_monitors(), event_loop.primary_monitor(), settings.monitor);
+        window_attributes = window_attributes.with_fullscreen(fullscreen(settings.mode, monitor));
+
+        // A missing icon isn't worth failing to start over
+        match settings.icon.as_deref().map(load_icon).transpose() {
+            Ok(icon) => window_attributes.with_window_icon(icon),
+            Err(e) => {
+                warn!("{e}");
+                window_attributes
+            }
+        }
+    }
+
+    /// Starts managing a newly created window, the first added is the primary one
+    pub(crate) fn add(&mut self, window: Arc<Window>) {
+        if self.windows.is_empty() {
+            // The cursor can only be grabbed once the window exists
+            if let Err(e) = grab_cursor(&window, self.settings.cursor_grab) {
+                warn!("{e}");
+            }
+            window.set_cursor_visible(self.settings.cursor_visible);
+        }
+
+        self.windows.push(window);
+    }
+
+    pub(crate) fn remove(&mut self, window_id: WindowId) {
+        self.windows.retain(|window| window.id() != window_id);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.windows.clear();
+    }
+
+    /// Remembers the size the user gave the primary window, so it's restored after leaving fullscreen
+    pub(crate) fn on_resized(&mut self, window_id: WindowId, size: PhysicalSize<u32>) {
+        let Some(window) = self.get(window_id) else {
+            return;
+        };
+
+        if self.is_primary(window_id) && self.settings.mode == WindowMode::Windowed && size.width > 0 && size.height > 0 {
+            let size: LogicalSize<u32> = size.to_logical(window.scale_factor());
+            self.settings.size = [size.width, size.height];
+        }
+    }
+
+    /// Remembers where the user moved the primary window
+    pub(crate) fn on_moved(&mut self, window_id: WindowId, position: PhysicalPosition<i32>) {
+        let Some(window) = self.get(window_id) else {
+            return;
+        };
+
+        if self.is_primary(window_id) && self.settings.mode == WindowMode::Windowed {
+            let position: LogicalPosition<i32> = position.to_logical(window.scale_factor());
+            self.settings.position = Some([position.x, position.y]);
+        }
+    }
+}
+
+/// The monitor at `index`, or the primary monitor when there is no index or nothing at it
+fn select_monitor(
+    mut available: impl Iterator<Item = MonitorHandle>,
+    primary: Option<MonitorHandle>,
+    index: Option<usize>,
+) -> Option<MonitorHandle> {
+    let Some(index) = index else {
+        return primary;
+    };
+
+    match available.nth(index) {
+        Some(monitor) => Some(monitor),
+        None => {
+            warn!("Monitor {index} was not found, using the primary monitor");
+            primary
+        }
+    }
+}
+
+fn fullscreen(mode: WindowMode, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+    match mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+        WindowMode::Exclusive { size, refresh_rate_millihertz } => {
+            let video_mode = monitor
+                .as_ref()
+                .and_then(|monitor| select_video_mode(monitor, size, refresh_rate_millihertz));
+
+            match video_mode {
+                Some(video_mode) => {
+                    debug!("Using exclusive fullscreen video mode {video_mode}");
+                    Some(Fullscreen::Exclusive(video_mode))
+                }
+                None => {
+                    warn!("No video mode matches {size:?} at {refresh_rate_millihertz:?} mHz, falling back to borderless");
+                    Some(Fullscreen::Borderless(monitor))
+                }
+            }
+        }
+    }
+}
+
+/// The largest, fastest video mode matching whichever of `size` and `refresh_rate_millihertz` are given
+fn select_video_mode(monitor: &MonitorHandle, size: Option<[u32; 2]>, refresh_rate_millihertz: Option<u32>) -> Option<VideoModeHandle> {
+    monitor
+        .video_modes()
+        .filter(|video_mode| size.is_none_or(|size| video_mode.size() == PhysicalSize::new(size[0], size[1])))
+        .filter(|video_mode| refresh_rate_millihertz.is_none_or(|rate| video_mode.refresh_rate_millihertz() == rate))
+        .max_by_key(|video_mode| {
+            let size = video_mode.size();
+            (size.width * size.height, video_mode.refresh_rate_millihertz(), video_mode.bit_depth())
+        })
+}
+
+fn grab_cursor(window: &Window, cursor_grab: CursorGrab) -> Result<(), WindowError> {
+    let result = match cursor_grab {
+        CursorGrab::None => window.set_cursor_grab(CursorGrabMode::None),
+        CursorGrab::Confined => window.set_cursor_grab(CursorGrabMode::Confined),
+        CursorGrab::Locked => window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined)),
+    };
+
+    Ok(result?)
+}
+
+fn load_icon(path: &Path) -> Result<Icon, WindowError> {
+    let image = image::open(path)
+        .map_err(|error| WindowError::IconDecodeError { path: path.to_path_buf(), error })?
+        .into_rgba8();
+
+    let (width, height) = image.dimensions();
+
+    Ok(Icon::from_rgba(image.into_raw(), width, height)?)
+}
@@ -0,0 +1,58 @@
+mod handle;
+mod server;
+mod texture;
+mod tracker;
+mod upload;
+
+pub use handle::*;
+pub use server::*;
+pub use texture::*;
+pub use tracker::AssetTracker;
+pub use upload::*;
+
+pub(crate) use tracker::Reupload;
+
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::device::Queue;
+use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::sync::Sharing;
+
+/// An asset is loaded from a file in two steps: reading it from disk and uploading it to the GPU
+pub trait Asset: Sized + Send + Sync + 'static {
+    /// The CPU side data read from disk
+    type Source: Send + Sync + 'static;
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn read(path: &Path) -> Result<Self::Source, Self::Error>;
+    /// Assets keep `source` so they can be uploaded again if the device is lost
+    fn upload(source: Arc<Self::Source>, context: &AssetContext) -> Result<Self, Self::Error>;
+}
+
+/// Everything an asset needs to upload itself to the GPU
+#[derive(Clone)]
+pub struct AssetContext {
+    pub memory_allocator: Arc<dyn MemoryAllocator>,
+    pub command_buffer_allocator: Arc<dyn CommandBufferAllocator>,
+    /// The graphics queue, for upload work a transfer queue can't do such as blits
+    pub queue: Arc<Queue>,
+    /// A dedicated transfer queue when the device has one, otherwise the graphics queue
+    pub transfer_queue: Arc<Queue>,
+    /// Uploaded assets register here so they survive the device being recreated
+    pub(crate) tracker: Arc<AssetTracker>,
+}
+
+impl AssetContext {
+    /// Sharing for resources written by the transfer queue and read by the graphics queue
+    pub fn sharing<F: FromIterator<u32>>(&self) -> Sharing<F> {
+        let graphics_family = self.queue.queue_family_index();
+        let transfer_family = self.transfer_queue.queue_family_index();
+
+        if graphics_family == transfer_family {
+            Sharing::Exclusive
+        } else {
+            Sharing::Concurrent([graphics_family, transfer_family].into_iter().collect())
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::assets::Asset;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AssetState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+pub(crate) enum SlotState<T: Asset> {
+    Loading,
+    Loaded(Arc<T>),
+    Failed(Arc<T::Error>),
+}
+
+/// The shared storage behind every handle to the same asset
+pub(crate) struct AssetSlot<T: Asset> {
+    path: PathBuf,
+    state: RwLock<SlotState<T>>,
+}
+
+impl<T: Asset> AssetSlot<T> {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            state: RwLock::new(SlotState::Loading),
+        }
+    }
+
+    pub(crate) fn set(&self, state: SlotState<T>) {
+        *self.state.write().unwrap() = state;
+    }
+
+    pub(crate) fn is_loaded(&self) -> bool {
+        matches!(*self.state.read().unwrap(), SlotState::Loaded(_))
+    }
+}
+
+/// A cheap reference to an asset owned by the `AssetServer`.
+/// The asset, and any GPU memory it holds, is freed when the last handle is dropped
+pub struct Handle<T: Asset> {
+    slot: Arc<AssetSlot<T>>,
+}
+
+impl<T: Asset> Handle<T> {
+    pub(crate) fn from_slot(slot: Arc<AssetSlot<T>>) -> Self {
+        Self { slot }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.slot.path
+    }
+
+    pub fn state(&self) -> AssetState {
+        match *self.slot.state.read().unwrap() {
+            SlotState::Loading => AssetState::Loading,
+            SlotState::Loaded(_) => AssetState::Loaded,
+            SlotState::Failed(_) => AssetState::Failed,
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.state() == AssetState::Loaded
+    }
+
+    /// Returns the asset if it has finished loading.
+    /// The asset may be swapped for a newer version when hot reloading, so don't hold on to it across frames
+    pub fn get(&self) -> Option<Arc<T>> {
+        match &*self.slot.state.read().unwrap() {
+            SlotState::Loaded(asset) => Some(asset.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the error if the asset failed to load
+    pub fn error(&self) -> Option<Arc<T::Error>> {
+        match &*self.slot.state.read().unwrap() {
+            SlotState::Failed(error) => Some(error.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Asset> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T: Asset> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.slot, &other.slot)
+    }
+}
+
+impl<T: Asset> Eq for Handle<T> {}
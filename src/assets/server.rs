@@ -0,0 +1,185 @@
+use crate::assets::{Asset, AssetContext, AssetSlot, Handle, SlotState};
+use log::{debug, error, info};
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AssetServerError {
+    #[error("failed to create asset loading threads! {0}")]
+    ThreadPoolError(#[from] ThreadPoolBuildError),
+    #[error("failed to start asset watcher! {0}")]
+    WatcherError(#[from] std::io::Error),
+}
+
+type AssetKey = (TypeId, PathBuf);
+
+struct CacheEntry {
+    slot: Weak<dyn Any + Send + Sync>,
+    modified: Option<SystemTime>,
+    reload: fn(&Arc<AssetServerShared>, &Weak<dyn Any + Send + Sync>, PathBuf),
+}
+
+/// State shared between the server and its watcher thread
+struct AssetServerShared {
+    // Shared with the asset tracker, which points it at each new device
+    context: Arc<RwLock<AssetContext>>,
+    thread_pool: ThreadPool,
+    cache: Mutex<HashMap<AssetKey, CacheEntry>>,
+}
+
+/// Loads assets in the background and hands out deduplicated handles to them
+pub struct AssetServer {
+    shared: Arc<AssetServerShared>,
+}
+
+impl AssetServer {
+    pub fn new(context: AssetContext) -> Result<Self, AssetServerError> {
+        let thread_pool = ThreadPoolBuilder::new()
+            .thread_name(|idx| format!("icarus-assets-{idx}"))
+            .build()?;
+
+        let tracker = context.tracker.clone();
+        let context = Arc::new(RwLock::new(context));
+        tracker.track_context(Arc::downgrade(&context));
+
+        Ok(Self {
+            shared: Arc::new(AssetServerShared {
+                context,
+                thread_pool,
+                cache: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Creates a server that polls the source files of loaded assets every `poll_interval`
+    /// and reloads any that have changed
+    pub fn with_hot_reload(context: AssetContext, poll_interval: Duration) -> Result<Self, AssetServerError> {
+        let server = Self::new(context)?;
+        let shared = Arc::downgrade(&server.shared);
+
+        thread::Builder::new()
+            .name(String::from("icarus-asset-watcher"))
+            .spawn(move || {
+                // The watcher stops once the server has been dropped
+                while let Some(shared) = shared.upgrade() {
+                    shared.reload_changed();
+                    drop(shared);
+                    thread::sleep(poll_interval);
+                }
+            })?;
+
+        info!("Watching assets for changes every {poll_interval:?}");
+
+        Ok(server)
+    }
+
+    /// Returns a handle to the asset at `path`, starting a load if it is not already loaded or loading
+    pub fn load<T: Asset, P: AsRef<Path>>(&self, path: P) -> Handle<T> {
+        let path = std::fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_path_buf());
+        let key = (TypeId::of::<T>(), path.clone());
+
+        let mut cache = self.shared.cache.lock().unwrap();
+
+        if let Some(slot) = cache
+            .get(&key)
+            .and_then(|entry| entry.slot.upgrade())
+            .and_then(|slot| slot.downcast::<AssetSlot<T>>().ok())
+        {
+            return Handle::from_slot(slot);
+        }
+
+        // Nobody holds these anymore, so their assets have already been freed
+        cache.retain(|_, entry| entry.slot.strong_count() > 0);
+
+        let slot = Arc::new(AssetSlot::<T>::new(path.clone()));
+        let weak_slot: Weak<dyn Any + Send + Sync> = Arc::downgrade(&slot);
+        cache.insert(key, CacheEntry {
+            slot: weak_slot,
+            modified: modified_time(&path),
+            reload: reload_slot::<T>,
+        });
+        drop(cache);
+
+        self.shared.spawn_load(Arc::downgrade(&slot), path, false);
+
+        Handle::from_slot(slot)
+    }
+
+    /// Points future loads at another context. The renderer already does this whenever it creates
+    /// a new device, and restores assets that are already loaded
+    pub fn set_context(&self, context: AssetContext) {
+        *self.shared.context.write().unwrap() = context;
+    }
+}
+
+impl AssetServerShared {
+    fn reload_changed(self: &Arc<Self>) {
+        let mut changed = Vec::new();
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, entry| entry.slot.strong_count() > 0);
+
+            for ((_, path), entry) in cache.iter_mut() {
+                let modified = modified_time(path);
+                if modified.is_some() && modified != entry.modified {
+                    entry.modified = modified;
+                    changed.push((entry.reload, entry.slot.clone(), path.clone()));
+                }
+            }
+        }
+
+        for (reload, slot, path) in changed {
+            info!("Asset {} changed, reloading", path.display());
+            reload(self, &slot, path);
+        }
+    }
+
+    fn spawn_load<T: Asset>(&self, slot: Weak<AssetSlot<T>>, path: PathBuf, is_reload: bool) {
+        let context = self.context.read().unwrap().clone();
+
+        self.thread_pool.spawn(move || {
+            // Don't bother loading if every handle was dropped while we were queued
+            if slot.strong_count() == 0 {
+                return;
+            }
+
+            let result = T::read(&path).and_then(|source| T::upload(Arc::new(source), &context));
+
+            let Some(slot) = slot.upgrade() else {
+                return;
+            };
+
+            match result {
+                Ok(asset) => {
+                    debug!("Loaded asset {}", path.display());
+                    slot.set(SlotState::Loaded(Arc::new(asset)));
+                }
+                Err(e) if is_reload && slot.is_loaded() => {
+                    // Keep the last good version around so a half-saved file doesn't break the game
+                    error!("Failed to reload asset {}, keeping the previous version! {e}", path.display());
+                }
+                Err(e) => {
+                    error!("Failed to load asset {}! {e}", path.display());
+                    slot.set(SlotState::Failed(Arc::new(e)));
+                }
+            }
+        });
+    }
+}
+
+fn reload_slot<T: Asset>(shared: &Arc<AssetServerShared>, slot: &Weak<dyn Any + Send + Sync>, path: PathBuf) {
+    if let Some(slot) = slot.upgrade().and_then(|slot| slot.downcast::<AssetSlot<T>>().ok()) {
+        shared.spawn_load(Arc::downgrade(&slot), path, true);
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,268 @@
+use crate::assets::{Asset, AssetContext, Reupload, Upload};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use vulkano::buffer::{AllocateBufferError, Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, CommandBufferExecError, CommandBufferUsage, CopyBufferToImageInfo, ImageBlit, PrimaryCommandBufferAbstract};
+use vulkano::descriptor_set::allocator::DescriptorSetAllocator;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::DeviceOwned;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode};
+use vulkano::image::view::ImageView;
+use vulkano::image::{AllocateImageError, Image, ImageCreateInfo, ImageLayout, ImageSubresourceLayers, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::sync::GpuFuture;
+use vulkano::{Validated, ValidationError, VulkanError};
+
+/// Higher levels cost bandwidth for barely visible gains
+const MAX_ANISOTROPY: f32 = 16.0;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TextureError {
+    #[error("failed to decode texture {}! {error}", path.display())]
+    DecodeError {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+    #[error("texture {} has no pixels", path.display())]
+    Empty {
+        path: PathBuf,
+    },
+    #[error("vulkan error! {0}")]
+    BufferAllocationError(#[from] Validated<AllocateBufferError>),
+    #[error("vulkan error! {0}")]
+    ImageAllocationError(#[from] Validated<AllocateImageError>),
+    #[error("vulkan error! {0}")]
+    VulkanError(#[from] Validated<VulkanError>),
+    #[error("failed to record texture upload! {0}")]
+    ValidationError(#[from] Box<ValidationError>),
+    #[error("failed to submit texture upload! {0}")]
+    ExecError(#[from] CommandBufferExecError),
+}
+
+/// RGBA8 pixels decoded from an image file
+pub struct TextureSource {
+    extent: [u32; 2],
+    pixels: Vec<u8>,
+}
+
+/// A sampled, mipmapped image living in device local memory
+pub struct Texture {
+    inner: Arc<TextureInner>,
+}
+
+/// Shared with the asset tracker so the texture can be restored on a new device
+struct TextureInner {
+    source: Arc<TextureSource>,
+    resources: RwLock<TextureResources>,
+}
+
+/// The device side of a texture, replaced whenever it is uploaded again
+struct TextureResources {
+    view: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+    upload: Upload,
+}
+
+impl Texture {
+    pub fn view(&self) -> Arc<ImageView> {
+        self.inner.resources.read().unwrap().view.clone()
+    }
+
+    pub fn sampler(&self) -> Arc<Sampler> {
+        self.inner.resources.read().unwrap().sampler.clone()
+    }
+
+    /// The upload of the current pixels, wait on it to block until the texture can be sampled
+    pub fn pending_upload(&self) -> Upload {
+        self.inner.resources.read().unwrap().upload.clone()
+    }
+
+    /// Whether the pixels have finished uploading and the texture can be sampled
+    pub fn is_ready(&self) -> bool {
+        self.inner.resources.read().unwrap().upload.is_complete()
+    }
+
+    /// Descriptor writes for a shader declaring the image at `binding` and its sampler at `binding + 1`
+    pub fn descriptor_writes(&self, binding: u32) -> [WriteDescriptorSet; 2] {
+        let resources = self.inner.resources.read().unwrap();
+
+        [
+            WriteDescriptorSet::image_view(binding, resources.view.clone()),
+            WriteDescriptorSet::sampler(binding + 1, resources.sampler.clone()),
+        ]
+    }
+
+    pub fn descriptor_set(
+        &self,
+        allocator: Arc<dyn DescriptorSetAllocator>,
+        layout: Arc<DescriptorSetLayout>,
+        binding: u32,
+    ) -> Result<Arc<DescriptorSet>, Validated<VulkanError>> {
+        DescriptorSet::new(allocator, layout, self.descriptor_writes(binding), [])
+    }
+
+    fn mip_levels(extent: [u32; 2]) -> u32 {
+        u32::BITS - extent[0].max(extent[1]).leading_zeros()
+    }
+}
+
+impl Asset for Texture {
+    type Source = TextureSource;
+    type Error = TextureError;
+
+    fn read(path: &Path) -> Result<TextureSource, TextureError> {
+        let image = image::open(path)
+            .map_err(|error| TextureError::DecodeError { path: path.to_path_buf(), error })?
+            .into_rgba8();
+
+        if image.width() == 0 || image.height() == 0 {
+            return Err(TextureError::Empty { path: path.to_path_buf() });
+        }
+
+        Ok(TextureSource {
+            extent: [image.width(), image.height()],
+            pixels: image.into_raw(),
+        })
+    }
+
+    fn upload(source: Arc<TextureSource>, context: &AssetContext) -> Result<Texture, TextureError> {
+        let resources = TextureResources::upload(&source, context)?;
+
+        let inner = Arc::new(TextureInner {
+            source,
+            resources: RwLock::new(resources),
+        });
+        let tracked: Weak<dyn Reupload> = Arc::downgrade(&inner);
+        context.tracker.track(tracked);
+
+        Ok(Texture { inner })
+    }
+}
+
+impl TextureResources {
+    fn upload(source: &TextureSource, context: &AssetContext) -> Result<Self, TextureError> {
+        let mip_levels = Texture::mip_levels(source.extent);
+
+        let staging_buffer = Buffer::from_iter(
+            context.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            source.pixels.iter().copied(),
+        )?;
+
+        let image = Image::new(
+            context.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R8G8B8A8_SRGB,
+                extent: [source.extent[0], source.extent[1], 1],
+                mip_levels,
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC | ImageUsage::SAMPLED,
+                sharing: context.sharing(),
+                ..ImageCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )?;
+
+        let mut transfer_builder = AutoCommandBufferBuilder::primary(
+            context.command_buffer_allocator.clone(),
+            context.transfer_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        transfer_builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(staging_buffer, image.clone()))?;
+
+        let copied = transfer_builder
+            .build()?
+            .execute(context.transfer_queue.clone())?;
+
+        // Blits need a graphics queue, so mipmaps are generated there once the copy is done
+        let mut builder = AutoCommandBufferBuilder::primary(
+            context.command_buffer_allocator.clone(),
+            context.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        // Each mip level is a linear downsample of the one above it
+        let mut mip_extent = source.extent;
+        for mip_level in 1..mip_levels {
+            let next_extent = [(mip_extent[0] / 2).max(1), (mip_extent[1] / 2).max(1)];
+
+            builder.blit_image(BlitImageInfo {
+                src_image_layout: ImageLayout::TransferSrcOptimal,
+                dst_image_layout: ImageLayout::TransferDstOptimal,
+                regions: [ImageBlit {
+                    src_subresource: ImageSubresourceLayers {
+                        mip_level: mip_level - 1,
+                        ..image.subresource_layers()
+                    },
+                    src_offsets: [[0, 0, 0], [mip_extent[0], mip_extent[1], 1]],
+                    dst_subresource: ImageSubresourceLayers {
+                        mip_level,
+                        ..image.subresource_layers()
+                    },
+                    dst_offsets: [[0, 0, 0], [next_extent[0], next_extent[1], 1]],
+                    ..ImageBlit::default()
+                }].into(),
+                filter: Filter::Linear,
+                ..BlitImageInfo::images(image.clone(), image.clone())
+            })?;
+
+            mip_extent = next_extent;
+        }
+
+        let upload = if mip_levels > 1 {
+            Upload::submit(copied.then_execute(context.queue.clone(), builder.build()?)?.boxed_send_sync())?
+        } else {
+            Upload::submit(copied.boxed_send_sync())?
+        };
+
+        let view = ImageView::new_default(image)?;
+
+        let device = context.queue.device();
+        // Anisotropy is an optional feature, only filter with it when the game asked and the device has it
+        let anisotropy = device.enabled_features().sampler_anisotropy
+            .then(|| device.physical_device().properties().max_sampler_anisotropy.min(MAX_ANISOTROPY));
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::Repeat; 3],
+                lod: 0.0..=mip_levels as f32,
+                anisotropy,
+                ..SamplerCreateInfo::default()
+            },
+        )?;
+
+        Ok(TextureResources { view, sampler, upload })
+    }
+}
+
+impl Reupload for TextureInner {
+    fn reupload(&self, context: &AssetContext) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let resources = TextureResources::upload(&self.source, context)?;
+        *self.resources.write().unwrap() = resources;
+
+        Ok(())
+    }
+
+    fn forget_upload(&self) {
+        self.resources.read().unwrap().upload.forget();
+    }
+}
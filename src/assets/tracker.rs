@@ -0,0 +1,83 @@
+use crate::assets::AssetContext;
+use log::{debug, error};
+use std::error::Error;
+use std::sync::{Mutex, RwLock, Weak};
+
+/// A GPU asset that kept the CPU data it was uploaded from
+pub(crate) trait Reupload: Send + Sync {
+    /// Replaces the asset's GPU resources with new ones uploaded through `context`
+    fn reupload(&self, context: &AssetContext) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Forgets the asset's pending upload without waiting on it, since the device it ran on is gone
+    fn forget_upload(&self);
+}
+
+/// Every live GPU asset and asset server context, so they can be moved to a new device when the
+/// device is lost or recreated
+#[derive(Default)]
+pub struct AssetTracker {
+    assets: Mutex<Vec<Weak<dyn Reupload>>>,
+    contexts: Mutex<Vec<Weak<RwLock<AssetContext>>>>,
+}
+
+impl AssetTracker {
+    pub(crate) fn track(&self, asset: Weak<dyn Reupload>) {
+        let mut assets = self.assets.lock().unwrap();
+
+        // Assets nobody holds anymore don't need restoring
+        assets.retain(|asset| asset.strong_count() > 0);
+        assets.push(asset);
+    }
+
+    /// Tracks the context an `AssetServer` loads through
+    pub(crate) fn track_context(&self, context: Weak<RwLock<AssetContext>>) {
+        let mut contexts = self.contexts.lock().unwrap();
+
+        contexts.retain(|context| context.strong_count() > 0);
+        contexts.push(context);
+    }
+
+    /// Points every live `AssetServer` at the device of `context`
+    pub(crate) fn set_context(&self, context: &AssetContext) {
+        for server_context in self.contexts.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            *server_context.write().unwrap() = context.clone();
+        }
+    }
+
+    /// Forgets the pending upload of every live asset, for when the device was lost
+    pub(crate) fn forget_uploads(&self) {
+        let assets: Vec<_> = self.assets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+
+        for asset in assets {
+            asset.forget_upload();
+        }
+    }
+
+    /// Uploads every live asset to the device of `context`, returning how many failed
+    pub(crate) fn reupload_all(&self, context: &AssetContext) -> usize {
+        // Upgrade first so uploads, which track nothing new, don't run under the lock
+        let assets: Vec<_> = self.assets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+
+        let failed = assets
+            .iter()
+            .filter_map(|asset| asset.reupload(context).err())
+            .inspect(|e| error!("Failed to restore an asset on the new device! {e}"))
+            .count();
+
+        match failed {
+            0 => debug!("Restored {} assets", assets.len()),
+            _ => error!("Restored {} of {} assets, {failed} failed", assets.len() - failed, assets.len()),
+        }
+
+        failed
+    }
+}
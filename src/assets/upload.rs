@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+use vulkano::sync::future::FenceSignalFuture;
+use vulkano::sync::GpuFuture;
+use vulkano::{Validated, VulkanError};
+
+type UploadFuture = FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>;
+
+/// GPU work copying an asset into device local memory.
+/// The asset must not be used until the upload completes
+#[derive(Clone)]
+pub struct Upload {
+    future: Arc<Mutex<Option<UploadFuture>>>,
+}
+
+impl Upload {
+    /// An upload that has nothing left to wait for
+    pub fn completed() -> Self {
+        Self {
+            future: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Submits `future`, signalling a fence once its work finishes
+    pub fn submit(future: Box<dyn GpuFuture + Send + Sync>) -> Result<Self, Validated<VulkanError>> {
+        Ok(Self {
+            future: Arc::new(Mutex::new(Some(future.then_signal_fence_and_flush()?))),
+        })
+    }
+
+    /// Whether the upload has finished, never blocks
+    pub fn is_complete(&self) -> bool {
+        let mut future = self.future.lock().unwrap();
+
+        let Some(pending) = future.as_ref() else {
+            return true;
+        };
+
+        match pending.is_signaled() {
+            Ok(true) => {
+                // Already signalled, so this only releases the resources the upload held
+                let is_complete = pending.wait(None).is_ok();
+                *future = None;
+                is_complete
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops the pending upload without waiting on its fence, for when the device was lost and
+    /// waiting could hang. Whatever the upload held is leaked
+    pub(crate) fn forget(&self) {
+        if let Some(pending) = self.future.lock().unwrap().take() {
+            std::mem::forget(pending);
+        }
+    }
+
+    /// Blocks until the upload has finished
+    pub fn wait(&self) -> Result<(), Validated<VulkanError>> {
+        let mut future = self.future.lock().unwrap();
+
+        if let Some(pending) = future.take() {
+            pending.wait(None)?;
+        }
+
+        Ok(())
+    }
+}
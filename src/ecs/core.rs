@@ -1,22 +1,27 @@
 pub mod archetypes {
-    use crate::ecs::core::components::{ModelData, Transform};
+    use crate::ecs::core::components::{Material, ModelData, Transform};
     use crate::ecs::Archetype;
 
     #[derive(Archetype)]
     pub struct Drawable {
         transform: Transform,
         model_data: ModelData,
+        material: Material,
     }
 }
 
 pub mod components {
-    use modelz::{Model3D, ModelError};
-    use std::path::Path;
-    use std::sync::Arc;
-    use vulkano::buffer::{AllocateBufferError, Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
-    use vulkano::device::DeviceOwned;
-    use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
-    use vulkano::Validated;
+    use crate::assets::{Asset, AssetContext, Handle, Reupload, Texture, Upload};
+    use crate::render::{CommandBuilder, PipelineKey, VertexLayout};
+    use modelz::{Indices, Mesh, Model3D, ModelError};
+    use std::error::Error;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, RwLock, Weak};
+    use vulkano::buffer::{AllocateBufferError, Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+    use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferExecError, CommandBufferUsage, CopyBufferInfo, PrimaryCommandBufferAbstract};
+    use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+    use vulkano::sync::GpuFuture;
+    use vulkano::{Validated, ValidationError, VulkanError};
 
     pub struct Transform {
         position: [f32; 3],
@@ -24,48 +29,324 @@ pub mod components {
         scale: [f32; 3],
     }
 
+    /// Describes how a model is shaded
+    #[derive(Clone)]
+    pub struct Material {
+        pub base_color: [f32; 4],
+        /// Bound in order, each texture taking an image and a sampler binding
+        pub textures: Vec<Handle<Texture>>,
+        pub vertex_shader: &'static str,
+        pub fragment_shader: &'static str,
+        pub vertex_layout: VertexLayout,
+    }
+
+    impl Material {
+        pub fn flat(base_color: [f32; 4]) -> Self {
+            Self {
+                base_color,
+                textures: Vec::new(),
+                vertex_shader: "main_vs",
+                fragment_shader: "main_fs",
+                vertex_layout: VertexLayout::Position,
+            }
+        }
+
+        pub fn textured(base_color: [f32; 4], texture: Handle<Texture>) -> Self {
+            Self {
+                base_color,
+                textures: vec![texture],
+                vertex_shader: "textured_vs",
+                fragment_shader: "textured_fs",
+                vertex_layout: VertexLayout::PositionTexCoord,
+            }
+        }
+
+        pub fn pipeline_key(&self) -> PipelineKey {
+            PipelineKey {
+                vertex_shader: self.vertex_shader,
+                fragment_shader: self.fragment_shader,
+                vertex_layout: self.vertex_layout,
+            }
+        }
+    }
+
+    /// A model's vertex data on the GPU. Clones share the same buffers
     #[derive(Clone)]
     pub struct ModelData {
+        inner: Arc<ModelDataInner>,
+    }
+
+    /// Shared with the asset tracker so the model can be restored on a new device
+    struct ModelDataInner {
+        source: Arc<ModelSource>,
+        buffers: RwLock<ModelBuffers>,
+    }
+
+    /// The device side of a model, replaced whenever it is uploaded again
+    struct ModelBuffers {
         model_data: Subbuffer<[[f32; 3]]>,
+        tex_coords: Option<Subbuffer<[[f32; 2]]>>,
+        indices: Option<Subbuffer<[u32]>>,
+        upload: Upload,
+    }
+
+    /// The vertex data of a model as read from disk
+    pub struct ModelSource {
+        positions: Vec<[f32; 3]>,
+        tex_coords: Option<Vec<[f32; 2]>>,
+        indices: Option<Vec<u32>>,
     }
 
     #[derive(thiserror::Error, Debug)]
     pub enum ModelDataError {
-        #[error("failed to load model!")]
-        ModelError(ModelError),
+        #[error("failed to load model {}! {error:?}", path.display())]
+        ModelError {
+            path: PathBuf,
+            error: ModelError,
+        },
+        #[error("model {} contains no meshes", path.display())]
+        NoMeshes {
+            path: PathBuf,
+        },
+        #[error("model {} contains no vertices", path.display())]
+        NoVertices {
+            path: PathBuf,
+        },
+        #[error("model {} has a non-finite position at vertex {vertex}", path.display())]
+        NonFinitePosition {
+            path: PathBuf,
+            vertex: usize,
+        },
+        #[error("model {} has index {index} out of range for {vertex_count} vertices", path.display())]
+        IndexOutOfRange {
+            path: PathBuf,
+            index: usize,
+            vertex_count: usize,
+        },
         #[error("vulkan error! {0}")]
         AllocationError(#[from] Validated<AllocateBufferError>),
+        #[error("vulkan error! {0}")]
+        VulkanError(#[from] Validated<VulkanError>),
+        #[error("failed to record model upload! {0}")]
+        ValidationError(#[from] Box<ValidationError>),
+        #[error("failed to submit model upload! {0}")]
+        ExecError(#[from] CommandBufferExecError),
     }
 
     impl ModelData {
-        pub fn teapot(memory_allocator: Arc<dyn MemoryAllocator>) -> Result<ModelData, ModelDataError> {
+        pub fn teapot(context: &AssetContext) -> Result<ModelData, ModelDataError> {
             let model_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("models").join("teapot.ply");
-            Self::from_path(memory_allocator, model_path)
+            Self::from_path(context, model_path)
         }
 
-        pub fn from_path<P: AsRef<Path>>(memory_allocator: Arc<dyn MemoryAllocator>, path: P) -> Result<ModelData, ModelDataError> {
-            let model = Model3D::load(path).map_err(ModelDataError::ModelError)?;
+        pub fn from_path<P: AsRef<Path>>(context: &AssetContext, path: P) -> Result<ModelData, ModelDataError> {
+            let source = Self::read_source(path.as_ref())?;
+            Self::upload_source(context, Arc::new(source))
+        }
 
-            let vertices = model.meshes
+        /// Reads and validates a model file without touching the GPU
+        pub fn read_source(path: &Path) -> Result<ModelSource, ModelDataError> {
+            let model = Model3D::load(path).map_err(|error| ModelDataError::ModelError {
+                path: path.to_path_buf(),
+                error,
+            })?;
+
+            let mesh = model.meshes
                 .first()
-                .unwrap()
+                .ok_or_else(|| ModelDataError::NoMeshes { path: path.to_path_buf() })?;
+
+            Self::validate_mesh(path, mesh)?;
+
+            let positions = mesh
                 .vertices
                 .iter()
-                .map(|x| x.position);
+                .map(|x| x.position)
+                .collect();
+
+            // Texture coordinates are all or nothing, a partially mapped mesh can't be sampled sensibly
+            let tex_coords = mesh
+                .vertices
+                .iter()
+                .map(|x| x.tex_coord)
+                .collect();
+
+            let indices = mesh.indices.as_ref().map(|indices| match indices {
+                Indices::U8(indices) => indices.iter().map(|&i| i as u32).collect(),
+                Indices::U16(indices) => indices.iter().map(|&i| i as u32).collect(),
+                Indices::U32(indices) => indices.clone(),
+            });
+
+            Ok(ModelSource { positions, tex_coords, indices })
+        }
+
+        /// Copies the vertex data into device local memory on the transfer queue without waiting for it,
+        /// the renderer skips the model until the upload completes. `source` is kept to restore the model
+        /// if the device is lost
+        pub fn upload_source(context: &AssetContext, source: Arc<ModelSource>) -> Result<ModelData, ModelDataError> {
+            let buffers = ModelBuffers::upload(context, &source)?;
+
+            let inner = Arc::new(ModelDataInner {
+                source,
+                buffers: RwLock::new(buffers),
+            });
+            let tracked: Weak<dyn Reupload> = Arc::downgrade(&inner);
+            context.tracker.track(tracked);
+
+            Ok(ModelData { inner })
+        }
+
+        pub fn positions(&self) -> Subbuffer<[[f32; 3]]> {
+            self.inner.buffers.read().unwrap().model_data.clone()
+        }
+
+        /// Texture coordinates, if every vertex of the model has them
+        pub fn tex_coords(&self) -> Option<Subbuffer<[[f32; 2]]>> {
+            self.inner.buffers.read().unwrap().tex_coords.clone()
+        }
+
+        /// The index buffer, if the mesh is indexed
+        pub fn indices(&self) -> Option<Subbuffer<[u32]>> {
+            self.inner.buffers.read().unwrap().indices.clone()
+        }
+
+        /// The upload of the current vertex data, wait on it to block until the model can be drawn
+        pub fn pending_upload(&self) -> Upload {
+            self.inner.buffers.read().unwrap().upload.clone()
+        }
 
-            Buffer::from_iter(
-                memory_allocator,
+        /// Whether the vertex data has finished uploading and the model can be drawn
+        pub fn is_ready(&self) -> bool {
+            self.inner.buffers.read().unwrap().upload.is_complete()
+        }
+
+        /// Checks a mesh is safe to upload, so a bad asset is an error rather than a crash or a GPU fault
+        fn validate_mesh(path: &Path, mesh: &Mesh) -> Result<(), ModelDataError> {
+            let vertex_count = mesh.vertices.len();
+
+            if vertex_count == 0 {
+                return Err(ModelDataError::NoVertices { path: path.to_path_buf() });
+            }
+
+            if let Some(vertex) = mesh.vertices
+                .iter()
+                .position(|x| !x.position.iter().all(|c| c.is_finite()))
+            {
+                return Err(ModelDataError::NonFinitePosition { path: path.to_path_buf(), vertex });
+            }
+
+            let out_of_range = match &mesh.indices {
+                Some(Indices::U8(indices)) => indices.iter().map(|&i| i as usize).find(|&i| i >= vertex_count),
+                Some(Indices::U16(indices)) => indices.iter().map(|&i| i as usize).find(|&i| i >= vertex_count),
+                Some(Indices::U32(indices)) => indices.iter().map(|&i| i as usize).find(|&i| i >= vertex_count),
+                None => None,
+            };
+
+            match out_of_range {
+                Some(index) => Err(ModelDataError::IndexOutOfRange { path: path.to_path_buf(), index, vertex_count }),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl ModelBuffers {
+        fn upload(context: &AssetContext, source: &ModelSource) -> Result<Self, ModelDataError> {
+            let mut builder = AutoCommandBufferBuilder::primary(
+                context.command_buffer_allocator.clone(),
+                context.transfer_queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )?;
+
+            let model_data = Self::device_buffer(context, &mut builder, BufferUsage::VERTEX_BUFFER, source.positions.iter().copied())?;
+
+            let tex_coords = source.tex_coords
+                .as_ref()
+                .map(|tex_coords| Self::device_buffer(context, &mut builder, BufferUsage::VERTEX_BUFFER, tex_coords.iter().copied()))
+                .transpose()?;
+
+            let indices = source.indices
+                .as_ref()
+                .map(|indices| Self::device_buffer(context, &mut builder, BufferUsage::INDEX_BUFFER, indices.iter().copied()))
+                .transpose()?;
+
+            let upload = Upload::submit(
+                builder
+                    .build()?
+                    .execute(context.transfer_queue.clone())?
+                    .boxed_send_sync(),
+            )?;
+
+            Ok(ModelBuffers { model_data, tex_coords, indices, upload })
+        }
+
+        /// Creates a device local buffer for `usage` and records copying `data` into it through a staging buffer
+        fn device_buffer<T, I>(
+            context: &AssetContext,
+            builder: &mut CommandBuilder,
+            usage: BufferUsage,
+            data: I,
+        ) -> Result<Subbuffer<[T]>, ModelDataError>
+        where
+            T: BufferContents,
+            I: IntoIterator<Item = T>,
+            I::IntoIter: ExactSizeIterator,
+        {
+            let staging_buffer = Buffer::from_iter(
+                context.memory_allocator.clone(),
                 BufferCreateInfo {
-                    usage: BufferUsage::VERTEX_BUFFER,
+                    usage: BufferUsage::TRANSFER_SRC,
                     ..BufferCreateInfo::default()
                 },
                 AllocationCreateInfo {
-                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
                         | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                     ..Default::default()
                 },
-                vertices,
-            ).map(|model_data| ModelData { model_data }).map_err(From::from)
+                data,
+            )?;
+
+            let buffer = Buffer::new_slice::<T>(
+                context.memory_allocator.clone(),
+                BufferCreateInfo {
+                    usage: usage | BufferUsage::TRANSFER_DST,
+                    sharing: context.sharing(),
+                    ..BufferCreateInfo::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+                staging_buffer.len(),
+            )?;
+
+            builder.copy_buffer(CopyBufferInfo::buffers(staging_buffer, buffer.clone()))?;
+
+            Ok(buffer)
+        }
+    }
+
+    impl Reupload for ModelDataInner {
+        fn reupload(&self, context: &AssetContext) -> Result<(), Box<dyn Error + Send + Sync>> {
+            let buffers = ModelBuffers::upload(context, &self.source)?;
+            *self.buffers.write().unwrap() = buffers;
+
+            Ok(())
+        }
+
+        fn forget_upload(&self) {
+            self.buffers.read().unwrap().upload.forget();
+        }
+    }
+
+    impl Asset for ModelData {
+        type Source = ModelSource;
+        type Error = ModelDataError;
+
+        fn read(path: &Path) -> Result<ModelSource, ModelDataError> {
+            Self::read_source(path)
+        }
+
+        fn upload(source: Arc<ModelSource>, context: &AssetContext) -> Result<ModelData, ModelDataError> {
+            Self::upload_source(context, source)
         }
     }
 }
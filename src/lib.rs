@@ -2,6 +2,7 @@
 #![feature(impl_trait_in_assoc_type)]
 
 pub mod app;
+pub mod assets;
 pub mod ecs;
 pub mod grid;
 pub mod render;
@@ -1 +1,5 @@
+mod graph;
+mod pipeline;
 
+pub use graph::*;
+pub use pipeline::*;
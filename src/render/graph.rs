@@ -0,0 +1,455 @@
+use std::sync::Arc;
+use thiserror::Error;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{AllocateImageError, Image, ImageCreateInfo, ImageLayout, ImageType, ImageUsage, SampleCount};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
+use vulkano::render_pass::SubpassDependency;
+use vulkano::sync::{AccessFlags, PipelineStages};
+use vulkano::{Validated, ValidationError, VulkanError};
+
+pub type CommandBuilder = AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>;
+
+#[derive(Error, Debug)]
+pub enum RenderGraphError {
+    #[error("render graph has a cycle between passes {0:?}")]
+    Cycle(Vec<&'static str>),
+    #[error("image {0} was used before it was set")]
+    MissingImage(&'static str),
+    #[error("image {0} is imported and has no description")]
+    ImportedImage(&'static str),
+    #[error("image {image} was not declared by pass {pass}")]
+    UndeclaredImage {
+        pass: &'static str,
+        image: &'static str,
+    },
+    #[error("failed to allocate render graph image! {0}")]
+    ImageAllocationError(#[from] Validated<AllocateImageError>),
+    #[error("vulkan error! {0}")]
+    VulkanError(#[from] Validated<VulkanError>),
+    #[error("failed to record render graph pass! {0}")]
+    ValidationError(#[from] Box<ValidationError>),
+    #[error("render graph pass {pass} failed! {error}")]
+    PassError {
+        pass: &'static str,
+        #[source]
+        error: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// Refers to an image within a single render graph
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+/// An image the graph allocates itself
+#[derive(Clone, Debug)]
+pub struct ImageDescription {
+    pub format: Format,
+    pub extent: [u32; 2],
+    pub samples: SampleCount,
+    pub usage: ImageUsage,
+}
+
+/// How a pass uses an image, which decides the layout the image must be in and what the pass has to wait for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    ColorAttachment,
+    DepthStencilAttachment,
+    Sampled,
+    TransferSrc,
+    TransferDst,
+}
+
+impl ImageAccess {
+    pub fn layout(&self) -> ImageLayout {
+        match self {
+            ImageAccess::ColorAttachment => ImageLayout::ColorAttachmentOptimal,
+            ImageAccess::DepthStencilAttachment => ImageLayout::DepthStencilAttachmentOptimal,
+            ImageAccess::Sampled => ImageLayout::ShaderReadOnlyOptimal,
+            ImageAccess::TransferSrc => ImageLayout::TransferSrcOptimal,
+            ImageAccess::TransferDst => ImageLayout::TransferDstOptimal,
+        }
+    }
+
+    fn stages(&self) -> PipelineStages {
+        match self {
+            ImageAccess::ColorAttachment => PipelineStages::COLOR_ATTACHMENT_OUTPUT,
+            ImageAccess::DepthStencilAttachment => PipelineStages::EARLY_FRAGMENT_TESTS | PipelineStages::LATE_FRAGMENT_TESTS,
+            ImageAccess::Sampled => PipelineStages::FRAGMENT_SHADER,
+            ImageAccess::TransferSrc | ImageAccess::TransferDst => PipelineStages::ALL_TRANSFER,
+        }
+    }
+
+    fn access(&self) -> AccessFlags {
+        match self {
+            ImageAccess::ColorAttachment => AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ImageAccess::DepthStencilAttachment => AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ImageAccess::Sampled => AccessFlags::SHADER_SAMPLED_READ,
+            ImageAccess::TransferSrc => AccessFlags::TRANSFER_READ,
+            ImageAccess::TransferDst => AccessFlags::TRANSFER_WRITE,
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        matches!(self, ImageAccess::ColorAttachment | ImageAccess::DepthStencilAttachment | ImageAccess::TransferDst)
+    }
+
+    /// Only writes have anything to make available to later accesses
+    fn written(&self) -> AccessFlags {
+        if self.is_write() { self.access() } else { AccessFlags::empty() }
+    }
+}
+
+/// How an image moves between layouts over a pass, from the accesses declared by the passes before and after it
+#[derive(Copy, Clone, Debug)]
+pub struct ImageTransition {
+    pub access: ImageAccess,
+    /// The access of the pass before this one, `None` when this is the first use of the image in a frame
+    pub previous: Option<ImageAccess>,
+    /// The access of the pass after this one, `None` when nothing uses the image afterwards
+    pub next: Option<ImageAccess>,
+}
+
+impl ImageTransition {
+    /// The layout the previous access left the image in, `Undefined` on first use as the contents are replaced
+    pub fn initial_layout(&self) -> ImageLayout {
+        self.previous.map_or(ImageLayout::Undefined, |previous| previous.layout())
+    }
+
+    /// The layout the next access needs, so the transition happens as part of this pass
+    pub fn final_layout(&self) -> ImageLayout {
+        self.next.unwrap_or(self.access).layout()
+    }
+}
+
+/// The transitions of every image a pass declared
+pub struct PassTransitions {
+    pass: &'static str,
+    image_names: Vec<&'static str>,
+    transitions: Vec<(ImageId, ImageTransition)>,
+}
+
+impl PassTransitions {
+    pub fn get(&self, id: ImageId) -> Result<ImageTransition, RenderGraphError> {
+        self.transitions
+            .iter()
+            .find(|(image, _)| *image == id)
+            .map(|&(_, transition)| transition)
+            .ok_or(RenderGraphError::UndeclaredImage { pass: self.pass, image: self.image_names[id.0] })
+    }
+
+    /// External dependencies for a render pass recording this pass, waiting on the earlier accesses of its images
+    /// and making the later accesses wait on it
+    pub fn dependencies(&self) -> Vec<SubpassDependency> {
+        let mut before = SubpassDependency {
+            src_subpass: None,
+            dst_subpass: Some(0),
+            ..SubpassDependency::default()
+        };
+        let mut after = SubpassDependency {
+            src_subpass: Some(0),
+            dst_subpass: None,
+            ..SubpassDependency::default()
+        };
+
+        for (_, transition) in self.transitions.iter() {
+            if let Some(previous) = transition.previous {
+                before.src_stages |= previous.stages();
+                before.src_access |= previous.written();
+                before.dst_stages |= transition.access.stages();
+                before.dst_access |= transition.access.access();
+            }
+            if let Some(next) = transition.next {
+                after.src_stages |= transition.access.stages();
+                after.src_access |= transition.access.written();
+                after.dst_stages |= next.stages();
+                after.dst_access |= next.access();
+            }
+        }
+
+        [before, after]
+            .into_iter()
+            .filter(|dependency| !dependency.src_stages.is_empty())
+            .collect()
+    }
+}
+
+/// The images of a render graph, with a view of each created alongside it.
+/// Transient images are available from pass setup onwards, imported ones once they have been set
+pub struct GraphImages {
+    names: Vec<&'static str>,
+    images: Vec<Option<(Arc<Image>, Arc<ImageView>)>>,
+}
+
+impl GraphImages {
+    pub fn get(&self, id: ImageId) -> Result<&Arc<Image>, RenderGraphError> {
+        self.images[id.0]
+            .as_ref()
+            .map(|(image, _)| image)
+            .ok_or(RenderGraphError::MissingImage(self.names[id.0]))
+    }
+
+    pub fn view(&self, id: ImageId) -> Result<Arc<ImageView>, RenderGraphError> {
+        self.images[id.0]
+            .as_ref()
+            .map(|(_, view)| view.clone())
+            .ok_or(RenderGraphError::MissingImage(self.names[id.0]))
+    }
+}
+
+/// Records a pass each frame, with `T` being whatever per frame data the graph's owner passes in
+pub type PassRecorder<T> = Box<dyn FnMut(&mut CommandBuilder, &GraphImages, &mut T) -> Result<(), RenderGraphError>>;
+
+type PassSetup<T> = Box<dyn FnOnce(&GraphImages, &PassTransitions) -> Result<PassRecorder<T>, RenderGraphError>>;
+
+struct PassEntry<T> {
+    name: &'static str,
+    accesses: Vec<(ImageId, ImageAccess)>,
+    setup: PassSetup<T>,
+}
+
+impl<T> PassEntry<T> {
+    fn reads(&self, id: ImageId) -> bool {
+        self.accesses.iter().any(|&(image, access)| image == id && !access.is_write())
+    }
+
+    fn writes(&self, id: ImageId) -> bool {
+        self.accesses.iter().any(|&(image, access)| image == id && access.is_write())
+    }
+}
+
+/// Collects passes and the images they use before a `RenderGraph` is built
+pub struct RenderGraphBuilder<T> {
+    image_names: Vec<&'static str>,
+    // `None` for imported images
+    image_descriptions: Vec<Option<ImageDescription>>,
+    passes: Vec<PassEntry<T>>,
+}
+
+impl<T> RenderGraphBuilder<T> {
+    pub fn new() -> Self {
+        Self {
+            image_names: Vec::new(),
+            image_descriptions: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Declares an image that is allocated when the graph is built
+    pub fn create_image(&mut self, name: &'static str, description: ImageDescription) -> ImageId {
+        self.image_names.push(name);
+        self.image_descriptions.push(Some(description));
+        ImageId(self.image_names.len() - 1)
+    }
+
+    /// The description of an image created with `create_image`
+    pub fn description(&self, id: ImageId) -> Result<&ImageDescription, RenderGraphError> {
+        self.image_descriptions[id.0]
+            .as_ref()
+            .ok_or(RenderGraphError::ImportedImage(self.image_names[id.0]))
+    }
+
+    /// Declares an image owned elsewhere, such as a swapchain image, which must be set before executing
+    pub fn import_image(&mut self, name: &'static str) -> ImageId {
+        self.image_names.push(name);
+        self.image_descriptions.push(None);
+        ImageId(self.image_names.len() - 1)
+    }
+
+    /// Adds a pass using each of `accesses`. `setup` runs once the graph's images are allocated, with the layout
+    /// transitions and dependencies of the declared images, and returns the recorder run every frame.
+    /// A pass runs after every pass that writes an image it reads, and after earlier added passes writing the same image
+    pub fn add_pass<S>(&mut self, name: &'static str, accesses: &[(ImageId, ImageAccess)], setup: S)
+    where
+        S: FnOnce(&GraphImages, &PassTransitions) -> Result<PassRecorder<T>, RenderGraphError> + 'static,
+    {
+        self.passes.push(PassEntry {
+            name,
+            accesses: accesses.to_vec(),
+            setup: Box::new(setup),
+        });
+    }
+
+    pub fn build(self, memory_allocator: Arc<dyn MemoryAllocator>) -> Result<RenderGraph<T>, RenderGraphError> {
+        let order = self.ordered_passes()?;
+
+        let images = self.image_descriptions
+            .iter()
+            .map(|description| description
+                .as_ref()
+                .map(|description| -> Result<_, RenderGraphError> {
+                    let image = Image::new(
+                        memory_allocator.clone(),
+                        ImageCreateInfo {
+                            image_type: ImageType::Dim2d,
+                            format: description.format,
+                            extent: [description.extent[0], description.extent[1], 1],
+                            samples: description.samples,
+                            usage: description.usage,
+                            ..ImageCreateInfo::default()
+                        },
+                        AllocationCreateInfo {
+                            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                            ..Default::default()
+                        },
+                    )?;
+                    let view = ImageView::new_default(image.clone())?;
+
+                    Ok((image, view))
+                })
+                .transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let images = GraphImages {
+            names: self.image_names,
+            images,
+        };
+
+        let transitions = Self::transitions(&self.passes, &order, &images.names);
+
+        let mut setups: Vec<_> = self.passes.into_iter().map(Some).collect();
+        let passes = order
+            .into_iter()
+            .zip(transitions)
+            .map(|(idx, transitions)| {
+                let pass = setups[idx].take().unwrap();
+                (pass.setup)(&images, &transitions).map(|recorder| (pass.name, recorder))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(RenderGraph { images, passes, imported_views: Vec::new() })
+    }
+
+    /// The transitions of each pass in `order`, pairing every declared access with the accesses to the same image
+    /// immediately before and after it
+    fn transitions(passes: &[PassEntry<T>], order: &[usize], names: &[&'static str]) -> Vec<PassTransitions> {
+        let accesses_of = |id: ImageId| -> Vec<ImageAccess> {
+            order
+                .iter()
+                .flat_map(|&idx| passes[idx].accesses.iter().filter(|(image, _)| *image == id).map(|&(_, access)| access))
+                .collect()
+        };
+
+        order
+            .iter()
+            .enumerate()
+            .map(|(position, &idx)| {
+                let pass = &passes[idx];
+                let transitions = pass.accesses
+                    .iter()
+                    .map(|&(id, access)| {
+                        // Accesses by earlier passes come first, so this pass's access sits right after them
+                        let earlier = order[..position]
+                            .iter()
+                            .flat_map(|&other| passes[other].accesses.iter().filter(|(image, _)| *image == id))
+                            .count();
+                        let accesses = accesses_of(id);
+
+                        (id, ImageTransition {
+                            access,
+                            previous: earlier.checked_sub(1).map(|previous| accesses[previous]),
+                            next: accesses.get(earlier + 1).copied(),
+                        })
+                    })
+                    .collect();
+
+                PassTransitions { pass: pass.name, image_names: names.to_vec(), transitions }
+            })
+            .collect()
+    }
+
+    /// Orders passes so that every pass runs after the passes it depends on, otherwise keeping the order they were added in
+    fn ordered_passes(&self) -> Result<Vec<usize>, RenderGraphError> {
+        let dependencies: Vec<Vec<usize>> = self.passes
+            .iter()
+            .enumerate()
+            .map(|(idx, pass)| {
+                self.passes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(other_idx, other)| {
+                        other_idx != idx && other.accesses.iter().any(|&(image, _)| {
+                            other.writes(image) && (pass.reads(image) || (other_idx < idx && pass.writes(image)))
+                        })
+                    })
+                    .map(|(other_idx, _)| other_idx)
+                    .collect()
+            })
+            .collect();
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut is_scheduled = vec![false; self.passes.len()];
+
+        while order.len() < self.passes.len() {
+            let next = (0..self.passes.len()).find(|&idx| {
+                !is_scheduled[idx] && dependencies[idx].iter().all(|&dependency| is_scheduled[dependency])
+            });
+
+            match next {
+                Some(idx) => {
+                    is_scheduled[idx] = true;
+                    order.push(idx);
+                }
+                None => {
+                    let cycle = (0..self.passes.len())
+                        .filter(|&idx| !is_scheduled[idx])
+                        .map(|idx| self.passes[idx].name)
+                        .collect();
+                    return Err(RenderGraphError::Cycle(cycle));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+}
+
+impl<T> Default for RenderGraphBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Passes in dependency order along with the images they use.
+/// Each pass is set up with the layout transitions and dependencies its declared accesses need, which passes
+/// drawing in a render pass build into its attachments and external dependencies
+pub struct RenderGraph<T> {
+    images: GraphImages,
+    passes: Vec<(&'static str, PassRecorder<T>)>,
+    // Views of every image imported so far, as the same few swapchain images come round again and again
+    imported_views: Vec<Arc<ImageView>>,
+}
+
+impl<T> RenderGraph<T> {
+    /// Sets an imported image for the following executions
+    pub fn set_image(&mut self, id: ImageId, image: Arc<Image>) -> Result<(), RenderGraphError> {
+        let view = match self.imported_views.iter().find(|view| Arc::ptr_eq(view.image(), &image)) {
+            Some(view) => view.clone(),
+            None => {
+                let view = ImageView::new_default(image.clone())?;
+                self.imported_views.push(view.clone());
+                view
+            }
+        };
+
+        self.images.images[id.0] = Some((image, view));
+
+        Ok(())
+    }
+
+    pub fn images(&self) -> &GraphImages {
+        &self.images
+    }
+
+    pub fn execute(&mut self, builder: &mut CommandBuilder, data: &mut T) -> Result<(), RenderGraphError> {
+        for (name, recorder) in self.passes.iter_mut() {
+            recorder(builder, &self.images, data).map_err(|error| match error {
+                RenderGraphError::PassError { .. } => error,
+                error => RenderGraphError::PassError { pass: *name, error: Box::new(error) },
+            })?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use vulkano::format::Format;
+use vulkano::pipeline::graphics::vertex_input::{VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate, VertexInputState};
+
+/// The vertex buffers a pipeline expects, one binding per attribute
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum VertexLayout {
+    /// No vertex buffers, the vertex shader makes its own positions
+    None,
+    Position,
+    PositionTexCoord,
+}
+
+impl VertexLayout {
+    pub(crate) fn vertex_input_state(&self) -> VertexInputState {
+        let attributes: &[(u32, Format)] = match self {
+            VertexLayout::None => &[],
+            VertexLayout::Position => &[(12, Format::R32G32B32_SFLOAT)],
+            VertexLayout::PositionTexCoord => &[(12, Format::R32G32B32_SFLOAT), (8, Format::R32G32_SFLOAT)],
+        };
+
+        attributes
+            .iter()
+            .enumerate()
+            .fold(VertexInputState::new(), |state, (idx, &(stride, format))| {
+                let idx = idx as u32;
+                state
+                    .binding(idx, VertexInputBindingDescription {
+                        stride,
+                        input_rate: VertexInputRate::Vertex,
+                        ..Default::default()
+                    })
+                    .attribute(idx, VertexInputAttributeDescription {
+                        binding: idx,
+                        format,
+                        offset: 0,
+                        ..Default::default()
+                    })
+            })
+    }
+}
+
+/// Everything that distinguishes one graphics pipeline from another
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PipelineKey {
+    pub vertex_shader: &'static str,
+    pub fragment_shader: &'static str,
+    pub vertex_layout: VertexLayout,
+}